@@ -0,0 +1,299 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::dmc::Dmc;
+use super::frame_counter::{FrameClocks, FrameCounter};
+use super::noise::Noise;
+use super::pulse::Pulse;
+use super::triangle::Triangle;
+
+/// NTSC 2A03 clock, the rate `Apu` produces samples at
+pub const CPU_CLOCK_RATE: f64 = 1_789_773.0;
+
+/// Raw level of every channel during one CPU cycle, mixing is left to the frontend.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct ChannelOutput {
+    pub pulse1: u8,
+    pub pulse2: u8,
+    pub triangle: u8,
+    pub noise: u8,
+    pub dmc: u8,
+}
+
+/// # Status register ($4015)
+///
+/// Write:
+///  7 6 5 4 3 2 1 0
+///  - - - D N T 2 1
+///        | | | | +--- Pulse 1 enable
+///        | | | +----- Pulse 2 enable
+///        | | +------- Triangle enable
+///        | +--------- Noise enable
+///        +----------- DMC enable
+///
+/// Read:
+///  7 6 5 4 3 2 1 0
+///  I F - D N T 2 1
+///  | |   | | | | +--- Pulse 1 length counter > 0
+///  | |   | | | +----- Pulse 2 length counter > 0
+///  | |   | | +------- Triangle length counter > 0
+///  | |   | +--------- Noise length counter > 0
+///  | |   +----------- DMC active
+///  | +--------------- Frame interrupt
+///  +----------------- DMC interrupt
+///
+pub struct Apu {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+
+    pub cycles: usize,
+    /// Keep a `ChannelOutput` for every CPU cycle until the frontend takes them
+    pub collect_samples: bool,
+    samples: Vec<ChannelOutput>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            cycles: 0,
+            collect_samples: false,
+            samples: vec![],
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000 => self.pulse1.write_control(data),
+            0x4001 => self.pulse1.write_sweep(data),
+            0x4002 => self.pulse1.write_timer_low(data),
+            0x4003 => self.pulse1.write_timer_high(data),
+            0x4004 => self.pulse2.write_control(data),
+            0x4005 => self.pulse2.write_sweep(data),
+            0x4006 => self.pulse2.write_timer_low(data),
+            0x4007 => self.pulse2.write_timer_high(data),
+            0x4008 => self.triangle.write_linear_counter(data),
+            // Unused, but part of the usual register clearing loops
+            0x4009 | 0x400d => {}
+            0x400a => self.triangle.write_timer_low(data),
+            0x400b => self.triangle.write_timer_high(data),
+            0x400c => self.noise.write_control(data),
+            0x400e => self.noise.write_period(data),
+            0x400f => self.noise.write_length(data),
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_direct_load(data),
+            0x4012 => self.dmc.write_sample_address(data),
+            0x4013 => self.dmc.write_sample_length(data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0b0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0100 != 0);
+                self.noise.length.set_enabled(data & 0b1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            0x4017 => {
+                let clocks = self.frame_counter.write(data);
+                self.clock_frame(clocks);
+            }
+            _ => println!("Ignoring APU write-access at {:#06x}", addr),
+        }
+    }
+
+    /// Reading the status acknowledges the frame interrupt.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_counter.clear_irq();
+        status
+    }
+
+    pub fn peek_status(&self) -> u8 {
+        let mut status = 0;
+        for (bit, active) in [
+            self.pulse1.length.is_active(),
+            self.pulse2.length.is_active(),
+            self.triangle.length.is_active(),
+            self.noise.length.is_active(),
+            self.dmc.is_active(),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            status |= (active as u8) << bit;
+        }
+        if self.frame_counter.irq_pending() {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq_pending() {
+            status |= 0b1000_0000;
+        }
+        status
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.frame_counter.irq_pending() || self.dmc.irq_pending()
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    fn clock(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        // Pulse timers run at APU rate, half the CPU clock
+        if self.cycles % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        let clocks = self.frame_counter.clock();
+        self.clock_frame(clocks);
+
+        self.cycles += 1;
+        if self.collect_samples {
+            self.samples.push(self.output());
+        }
+    }
+
+    fn clock_frame(&mut self, clocks: FrameClocks) {
+        if clocks.quarter {
+            self.pulse1.clock_quarter_frame();
+            self.pulse2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if clocks.half {
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+    }
+
+    pub fn output(&self) -> ChannelOutput {
+        ChannelOutput {
+            pulse1: self.pulse1.output(),
+            pulse2: self.pulse2.output(),
+            triangle: self.triangle.output(),
+            noise: self.noise.output(),
+            dmc: self.dmc.output(),
+        }
+    }
+
+    /// Hands out the samples collected since the last call, one per CPU cycle.
+    pub fn take_samples(&mut self) -> Vec<ChannelOutput> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+// Samples still waiting for the frontend are not part of the machine
+impl Savestate for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        self.frame_counter.save(w);
+        w.usize(self.cycles);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.frame_counter.load(r)?;
+        self.cycles = r.usize()?;
+        Ok(())
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_reports_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0101);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x4007, 0b0000_1000);
+        apu.write_register(0x400b, 0b0000_1000);
+
+        assert_eq!(apu.read_status(), 0b0000_0101);
+
+        apu.write_register(0x4015, 0b0000_0001);
+        assert_eq!(apu.read_status(), 0b0000_0001);
+    }
+
+    #[test]
+    fn test_status_read_clears_frame_irq() {
+        let mut apu = Apu::new();
+        apu.tick(29829);
+        assert!(apu.irq_pending());
+
+        assert_eq!(apu.read_status(), 0b0100_0000);
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_status_reports_dmc() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4015, 0b0001_0000);
+        assert_eq!(apu.read_status(), 0b0001_0000);
+
+        // A 1 byte sample ends as soon as it is fetched
+        apu.dmc.load_sample_byte(0);
+        assert_eq!(apu.read_status(), 0b1000_0000);
+        assert!(apu.irq_pending());
+
+        // Unlike the frame IRQ, only $4015 writes acknowledge it
+        assert_eq!(apu.read_status(), 0b1000_0000);
+        apu.write_register(0x4015, 0);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_collects_one_sample_per_cycle() {
+        let mut apu = Apu::new();
+        apu.tick(10);
+        assert!(apu.take_samples().is_empty());
+
+        apu.collect_samples = true;
+        apu.write_register(0x4015, 0b0000_0001);
+        // 50% duty, constant volume 12, period 8
+        apu.write_register(0x4000, 0b1001_1100);
+        apu.write_register(0x4002, 8);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.tick(36);
+
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 36);
+        assert_eq!(samples[0].pulse1, 0);
+        assert_eq!(samples[35].pulse1, 12);
+        assert!(apu.take_samples().is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+// NTSC periods in CPU cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// # Delta modulation channel https://www.nesdev.org/wiki/APU_DMC
+///
+/// Plays 1-bit delta encoded samples fetched from $C000-$FFFF. The memory
+/// reader does not own the bus, it asks for the next byte through
+/// `dma_request` and the `Bus` answers with `load_sample_byte`.
+pub struct Dmc {
+    irq_enabled: bool,
+    irq_pending: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+
+    // Memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    output_level: u8,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq_pending: false,
+            looping: false,
+            timer_period: RATE_TABLE[0],
+            timer: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            output_level: 0,
+        }
+    }
+
+    /// $4010: IL-- RRRR, clearing the IRQ enable also acknowledges the IRQ
+    pub fn write_control(&mut self, data: u8) {
+        self.irq_enabled = data & 0b1000_0000 != 0;
+        self.looping = data & 0b0100_0000 != 0;
+        self.timer_period = RATE_TABLE[(data & 0b1111) as usize];
+        if !self.irq_enabled {
+            self.irq_pending = false;
+        }
+    }
+
+    /// $4011: -DDD DDDD
+    pub fn write_direct_load(&mut self, data: u8) {
+        self.output_level = data & 0b0111_1111;
+    }
+
+    /// $4012: sample address %11AAAAAA.AA000000
+    pub fn write_sample_address(&mut self, data: u8) {
+        self.sample_address = 0xc000 | ((data as u16) << 6);
+    }
+
+    /// $4013: sample length %LLLL.LLLL0001
+    pub fn write_sample_length(&mut self, data: u8) {
+        self.sample_length = ((data as u16) << 4) | 1;
+    }
+
+    /// $4015 bit 4, enabling only restarts a sample that has finished.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_pending = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Address of the next sample byte, when the buffer is empty and the sample is not over.
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Completes a `dma_request`, moving on to the next byte of the sample.
+    pub fn load_sample_byte(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // The address wraps into $8000, not back to $C000
+        self.current_address = match self.current_address {
+            0xffff => 0x8000,
+            addr => addr + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc::new()
+    }
+}
+
+impl Savestate for Dmc {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_pending);
+        w.bool(self.looping);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.u16(self.sample_address);
+        w.u16(self.sample_length);
+        w.u16(self.current_address);
+        w.u16(self.bytes_remaining);
+        w.bool(self.sample_buffer.is_some());
+        w.u8(self.sample_buffer.unwrap_or(0));
+        w.u8(self.shift_register);
+        w.u8(self.bits_remaining);
+        w.bool(self.silence);
+        w.u8(self.output_level);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.irq_enabled = r.bool()?;
+        self.irq_pending = r.bool()?;
+        self.looping = r.bool()?;
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        self.sample_address = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.current_address = r.u16()?;
+        self.bytes_remaining = r.u16()?;
+        let has_sample = r.bool()?;
+        let sample = r.u8()?;
+        self.sample_buffer = has_sample.then_some(sample);
+        self.shift_register = r.u8()?;
+        self.bits_remaining = r.u8()?;
+        self.silence = r.bool()?;
+        self.output_level = r.u8()? & 0b0111_1111;
+        if self.timer_period == 0 || self.bits_remaining == 0 {
+            return Err(StateReader::invalid("DMC timer", self.timer_period));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Answers the DMA requests from a fixed sample and returns the fetched addresses
+    fn run(dmc: &mut Dmc, sample: &[u8], cycles: usize) -> Vec<u16> {
+        let mut fetched = vec![];
+        for _ in 0..cycles {
+            dmc.clock_timer();
+            if let Some(addr) = dmc.dma_request() {
+                fetched.push(addr);
+                dmc.load_sample_byte(sample[(addr - 0xc000) as usize % sample.len()]);
+            }
+        }
+        fetched
+    }
+
+    #[test]
+    fn test_plays_deltas() {
+        let mut dmc = Dmc::new();
+        // Fastest rate, 1 byte at $C000
+        dmc.write_control(0x0f);
+        dmc.write_direct_load(64);
+        dmc.write_sample_length(0);
+        dmc.set_enabled(true);
+
+        // The first 8 bits drain the empty shift register, then 0b0000_1111 plays
+        run(&mut dmc, &[0b0000_1111], 54 * 8);
+        assert_eq!(dmc.output(), 64);
+        run(&mut dmc, &[0b0000_1111], 54 * 4);
+        assert_eq!(dmc.output(), 72);
+        run(&mut dmc, &[0b0000_1111], 54 * 4);
+        assert_eq!(dmc.output(), 64);
+    }
+
+    #[test]
+    fn test_irq_at_end_of_sample() {
+        let mut dmc = Dmc::new();
+        dmc.write_control(0x8f);
+        dmc.write_sample_address(0x01);
+        dmc.write_sample_length(1);
+        dmc.set_enabled(true);
+
+        let fetched = run(&mut dmc, &[0; 0x80], 54 * 8 * 20);
+        assert_eq!(fetched, (0xc040..0xc051).collect::<Vec<u16>>());
+        assert!(!dmc.is_active());
+        assert!(dmc.irq_pending());
+
+        dmc.write_control(0x0f);
+        assert!(!dmc.irq_pending());
+    }
+
+    #[test]
+    fn test_loop_restarts_sample() {
+        let mut dmc = Dmc::new();
+        dmc.write_control(0xcf);
+        dmc.set_enabled(true);
+
+        let fetched = run(&mut dmc, &[0], 54 * 8 * 2);
+        assert_eq!(fetched, vec![0xc000; 3]);
+        assert!(dmc.is_active());
+        assert!(!dmc.irq_pending());
+    }
+
+    #[test]
+    fn test_address_wraps_to_8000() {
+        let mut dmc = Dmc::new();
+        dmc.write_sample_address(0xff);
+        dmc.write_sample_length(1);
+        dmc.set_enabled(true);
+        dmc.current_address = 0xffff;
+
+        dmc.load_sample_byte(0);
+        assert_eq!(dmc.current_address, 0x8000);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+/// Volume envelope of the pulse and noise channels https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    // The constant volume, or the period of the decay divider
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope::default()
+    }
+
+    /// Takes the low bits of the channel's first register: --LC VVVV
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant_volume = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+
+    /// Restarts the decay from 15 on the next quarter frame.
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Quarter frame clock
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl Savestate for Envelope {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.start);
+        w.bool(self.looping);
+        w.bool(self.constant_volume);
+        w.u8(self.volume);
+        w.u8(self.divider);
+        w.u8(self.decay);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.start = r.bool()?;
+        self.looping = r.bool()?;
+        self.constant_volume = r.bool()?;
+        self.volume = r.u8()?;
+        self.divider = r.u8()?;
+        self.decay = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decay() {
+        let mut envelope = Envelope::new();
+        // Divider period 1: the level drops every second clock
+        envelope.write(0b0000_0001);
+        envelope.restart();
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+
+        for _ in 0..40 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+    }
+
+    #[test]
+    fn test_constant_volume() {
+        let mut envelope = Envelope::new();
+        envelope.write(0b0001_0111);
+        envelope.restart();
+        envelope.clock();
+
+        assert_eq!(envelope.output(), 7);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut envelope = Envelope::new();
+        envelope.write(0b0010_0000);
+        envelope.restart();
+
+        for _ in 0..16 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+}
@@ -0,0 +1,112 @@
+use std::f32::consts::PI;
+
+/// First order high-pass filter
+pub struct HighPass {
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+    pub fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        HighPass {
+            alpha: rc / (rc + dt),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.previous_output = self.alpha * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output
+    }
+}
+
+/// First order low-pass filter
+pub struct LowPass {
+    alpha: f32,
+    previous_output: f32,
+}
+
+impl LowPass {
+    pub fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        LowPass {
+            alpha: dt / (rc + dt),
+            previous_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.previous_output += self.alpha * (input - self.previous_output);
+        self.previous_output
+    }
+}
+
+/// The filters between the APU and the audio out jack of an NES
+/// https://www.nesdev.org/wiki/APU_Mixer
+pub struct FilterChain {
+    high_pass_90: HighPass,
+    high_pass_440: HighPass,
+    low_pass_14k: LowPass,
+}
+
+impl FilterChain {
+    pub fn new(sample_rate: f32) -> Self {
+        FilterChain {
+            high_pass_90: HighPass::new(90.0, sample_rate),
+            high_pass_440: HighPass::new(440.0, sample_rate),
+            low_pass_14k: LowPass::new(14_000.0, sample_rate),
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.high_pass_90.process(input);
+        let output = self.high_pass_440.process(output);
+        self.low_pass_14k.process(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut filter = HighPass::new(90.0, 48_000.0);
+        let mut output = 1.0;
+        for _ in 0..48_000 {
+            output = filter.process(0.5);
+        }
+        assert!(output.abs() < 0.001);
+    }
+
+    #[test]
+    fn test_low_pass_keeps_dc() {
+        let mut filter = LowPass::new(14_000.0, 48_000.0);
+        let mut output = 0.0;
+        for _ in 0..100 {
+            output = filter.process(0.5);
+        }
+        assert!((output - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_chain_centers_signal() {
+        let mut chain = FilterChain::new(44_100.0);
+        // A 1 kHz square wave between 0 and 0.2, the way the DAC outputs it
+        let samples: Vec<f32> = (0..44_100)
+            .map(|n| chain.process(if (n / 22) % 2 == 0 { 0.2 } else { 0.0 }))
+            .collect();
+        let tail = &samples[22_050..];
+        let mean = tail.iter().sum::<f32>() / tail.len() as f32;
+
+        assert!(mean.abs() < 0.005);
+        assert!(tail.iter().any(|&sample| sample > 0.05));
+        assert!(tail.iter().any(|&sample| sample < -0.05));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+/// Quarter and half frame clocks for the envelopes, sweeps and counters
+#[derive(Default, Debug, PartialEq)]
+pub struct FrameClocks {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+impl FrameClocks {
+    const QUARTER: FrameClocks = FrameClocks {
+        quarter: true,
+        half: false,
+    };
+    const BOTH: FrameClocks = FrameClocks {
+        quarter: true,
+        half: true,
+    };
+}
+
+// Step boundaries in CPU cycles (NTSC)
+const STEP_1: usize = 7457;
+const STEP_2: usize = 14913;
+const STEP_3: usize = 22371;
+const STEP_4: usize = 29829;
+const STEP_5: usize = 37281;
+
+/// # Frame counter ($4017) https://www.nesdev.org/wiki/APU_Frame_Counter
+///
+///  7 6 5 4 3 2 1 0
+///  M I - - - - - -
+///  | |
+///  | +--------------- IRQ inhibit
+///  +----------------- Sequencer mode (0: 4-step, 1: 5-step)
+///
+#[derive(Default)]
+pub struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    irq_pending: bool,
+    cycle: usize,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter::default()
+    }
+
+    /// Restarts the sequence, the 5-step mode clocks everything right away.
+    pub fn write(&mut self, data: u8) -> FrameClocks {
+        self.five_step = data & 0b1000_0000 != 0;
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq_pending = false;
+        }
+        self.cycle = 0;
+
+        if self.five_step {
+            FrameClocks::BOTH
+        } else {
+            FrameClocks::default()
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock(&mut self) -> FrameClocks {
+        self.cycle += 1;
+        match (self.five_step, self.cycle) {
+            (_, STEP_1) | (_, STEP_3) => FrameClocks::QUARTER,
+            (_, STEP_2) => FrameClocks::BOTH,
+            (false, STEP_4) => {
+                if !self.irq_inhibit {
+                    self.irq_pending = true;
+                }
+                self.cycle = 0;
+                FrameClocks::BOTH
+            }
+            (true, STEP_5) => {
+                self.cycle = 0;
+                FrameClocks::BOTH
+            }
+            _ => FrameClocks::default(),
+        }
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Reading $4015 acknowledges the IRQ
+    pub fn clear_irq(&mut self) {
+        self.irq_pending = false;
+    }
+}
+
+impl Savestate for FrameCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.five_step);
+        w.bool(self.irq_inhibit);
+        w.bool(self.irq_pending);
+        w.usize(self.cycle);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.five_step = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.irq_pending = r.bool()?;
+        self.cycle = r.usize()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn clocks_until(counter: &mut FrameCounter, cycles: usize) -> (usize, usize) {
+        let (mut quarters, mut halves) = (0, 0);
+        for _ in 0..cycles {
+            let clocks = counter.clock();
+            quarters += clocks.quarter as usize;
+            halves += clocks.half as usize;
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut counter = FrameCounter::new();
+
+        assert_eq!(clocks_until(&mut counter, STEP_4 - 1), (3, 1));
+        assert!(!counter.irq_pending());
+        assert_eq!(clocks_until(&mut counter, 1), (1, 1));
+        assert!(counter.irq_pending());
+
+        counter.clear_irq();
+        assert_eq!(clocks_until(&mut counter, STEP_4), (4, 2));
+        assert!(counter.irq_pending());
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut counter = FrameCounter::new();
+        assert_eq!(counter.write(0x80), FrameClocks::BOTH);
+
+        assert_eq!(clocks_until(&mut counter, STEP_5), (4, 2));
+        assert!(!counter.irq_pending());
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut counter = FrameCounter::new();
+        clocks_until(&mut counter, STEP_4);
+        assert!(counter.irq_pending());
+
+        counter.write(0x40);
+        assert!(!counter.irq_pending());
+        clocks_until(&mut counter, STEP_4);
+        assert!(!counter.irq_pending());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+// Indexed by the upper 5 bits of the channel's length register
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel after a programmed duration https://www.nesdev.org/wiki/APU_Length_Counter
+#[derive(Default)]
+pub struct LengthCounter {
+    // Channel enable bit in $4015
+    enabled: bool,
+    halted: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter::default()
+    }
+
+    /// Disabling the channel also clears the counter.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Loads the counter from the table, ignored while the channel is disabled.
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0b1_1111) as usize];
+        }
+    }
+
+    /// Half frame clock
+    pub fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+impl Savestate for LengthCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.halted);
+        w.u8(self.counter);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.enabled = r.bool()?;
+        self.halted = r.bool()?;
+        self.counter = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_requires_enable() {
+        let mut length = LengthCounter::new();
+        length.load(1);
+        assert!(!length.is_active());
+
+        length.set_enabled(true);
+        length.load(3);
+        assert!(length.is_active());
+        length.clock();
+        length.clock();
+        assert!(!length.is_active());
+    }
+
+    #[test]
+    fn test_halt_and_disable() {
+        let mut length = LengthCounter::new();
+        length.set_enabled(true);
+        length.load(3);
+        length.set_halted(true);
+        length.clock();
+        length.clock();
+        assert!(length.is_active());
+
+        length.set_enabled(false);
+        assert!(!length.is_active());
+    }
+}
@@ -0,0 +1,81 @@
+use super::apu::ChannelOutput;
+
+/// Non-linear DAC of the 2A03 https://www.nesdev.org/wiki/APU_Mixer
+///
+/// Both pulses share one resistor ladder and the triangle, noise and DMC
+/// another, so a channel gets quieter the louder the others on its ladder are.
+/// Returns a level between 0.0 and about 1.0.
+pub fn mix(output: ChannelOutput) -> f32 {
+    pulse_out(output.pulse1, output.pulse2) + tnd_out(output.triangle, output.noise, output.dmc)
+}
+
+fn pulse_out(pulse1: u8, pulse2: u8) -> f32 {
+    let pulse = pulse1 as f32 + pulse2 as f32;
+    if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    }
+}
+
+fn tnd_out(triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.0005,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_silence() {
+        assert_eq!(mix(ChannelOutput::default()), 0.0);
+    }
+
+    #[test]
+    fn test_full_scale() {
+        let pulses = ChannelOutput {
+            pulse1: 15,
+            pulse2: 15,
+            ..Default::default()
+        };
+        assert_close(mix(pulses), 0.2585);
+
+        let all = ChannelOutput {
+            pulse1: 15,
+            pulse2: 15,
+            triangle: 15,
+            noise: 15,
+            dmc: 127,
+        };
+        assert_close(mix(all), 1.0);
+    }
+
+    #[test]
+    fn test_pulses_are_not_linear() {
+        let one = ChannelOutput {
+            pulse1: 15,
+            ..Default::default()
+        };
+        let both = ChannelOutput {
+            pulse1: 15,
+            pulse2: 15,
+            ..Default::default()
+        };
+
+        assert!(mix(both) < 2.0 * mix(one));
+    }
+}
@@ -0,0 +1,13 @@
+pub mod apu;
+pub mod dmc;
+pub mod envelope;
+pub mod filter;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mixer;
+pub mod noise;
+pub mod pulse;
+pub mod resampler;
+pub mod sound;
+pub mod triangle;
+pub mod wav;
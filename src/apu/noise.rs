@@ -0,0 +1,151 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+// NTSC periods in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// Pseudo-random noise channel https://www.nesdev.org/wiki/APU_Noise
+pub struct Noise {
+    shift_register: u16,
+    // Short mode taps bit 6 instead of bit 1, looping after 31 or 93 steps
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            shift_register: 1,
+            short_mode: false,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            length: LengthCounter::new(),
+            envelope: Envelope::new(),
+        }
+    }
+
+    /// $400C: --LC VVVV
+    pub fn write_control(&mut self, data: u8) {
+        self.length.set_halted(data & 0b0010_0000 != 0);
+        self.envelope.write(data);
+    }
+
+    /// $400E: M--- PPPP
+    pub fn write_period(&mut self, data: u8) {
+        self.short_mode = data & 0b1000_0000 != 0;
+        self.timer_period = PERIOD_TABLE[(data & 0b1111) as usize];
+    }
+
+    /// $400F: LLLL L---
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data >> 3);
+        self.envelope.restart();
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || !self.length.is_active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise::new()
+    }
+}
+
+impl Savestate for Noise {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.shift_register);
+        w.bool(self.short_mode);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        Savestate::save(&self.length, w);
+        self.envelope.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.shift_register = r.u16()?;
+        self.short_mode = r.bool()?;
+        self.timer_period = r.u16()?;
+        if self.timer_period == 0 {
+            return Err(StateReader::invalid("noise period", 0));
+        }
+        self.timer = r.u16()?;
+        Savestate::load(&mut self.length, r)?;
+        self.envelope.load(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sequence_length(short_mode: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.write_period(if short_mode { 0x80 } else { 0 });
+        let start = noise.shift_register;
+        let mut steps = 0;
+        loop {
+            for _ in 0..4 {
+                noise.clock_timer();
+            }
+            steps += 1;
+            if noise.shift_register == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lfsr_periods() {
+        assert_eq!(sequence_length(false), 32767);
+        assert_eq!(sequence_length(true), 93);
+    }
+
+    #[test]
+    fn test_output_follows_bit_0() {
+        let mut noise = Noise::new();
+        noise.length.set_enabled(true);
+        noise.write_control(0b0001_1001);
+        noise.write_length(0b0000_1000);
+        assert_eq!(noise.output(), 0);
+
+        // 1 -> 0x4000 after the first clock, bit 0 clear
+        noise.clock_timer();
+        assert_eq!(noise.output(), 9);
+    }
+}
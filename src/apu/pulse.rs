@@ -0,0 +1,235 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Square wave channel https://www.nesdev.org/wiki/APU_Pulse
+pub struct Pulse {
+    // Pulse 1 negates its sweep with ones' complement, pulse 2 with two's complement
+    ones_complement: bool,
+    duty: u8,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            length: LengthCounter::new(),
+            envelope: Envelope::new(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    /// $4000/$4004: DDLC VVVV
+    pub fn write_control(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.set_halted(data & 0b0010_0000 != 0);
+        self.envelope.write(data);
+    }
+
+    /// $4001/$4005: EPPP NSSS
+    pub fn write_sweep(&mut self, data: u8) {
+        self.sweep_enabled = data & 0b1000_0000 != 0;
+        self.sweep_period = (data >> 4) & 0b111;
+        self.sweep_negate = data & 0b0000_1000 != 0;
+        self.sweep_shift = data & 0b111;
+        self.sweep_reload = true;
+    }
+
+    /// $4002/$4006
+    pub fn write_timer_low(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x0700) | data as u16;
+    }
+
+    /// $4003/$4007: LLLL LTTT, restarts the sequence and the envelope
+    pub fn write_timer_high(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | (((data & 0b111) as u16) << 8);
+        self.length.load(data >> 3);
+        self.sequence_step = 0;
+        self.envelope.restart();
+    }
+
+    /// Clocked every APU cycle, i.e. every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.timer_period + change
+        } else if self.ones_complement {
+            self.timer_period.saturating_sub(change + 1)
+        } else {
+            self.timer_period.saturating_sub(change)
+        }
+    }
+
+    /// The sweep unit mutes the channel even while it is disabled.
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.target_period() > 0x07ff
+    }
+
+    pub fn output(&self) -> u8 {
+        let high = DUTY_TABLE[self.duty as usize][self.sequence_step as usize] != 0;
+        if !high || !self.length.is_active() || self.is_muted() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Savestate for Pulse {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.duty);
+        w.u8(self.sequence_step);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        Savestate::save(&self.length, w);
+        self.envelope.save(w);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_period);
+        w.bool(self.sweep_negate);
+        w.u8(self.sweep_shift);
+        w.u8(self.sweep_divider);
+        w.bool(self.sweep_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.duty = r.u8()? & 0b11;
+        self.sequence_step = r.u8()? & 0b111;
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        Savestate::load(&mut self.length, r)?;
+        self.envelope.load(r)?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_period = r.u8()?;
+        self.sweep_negate = r.bool()?;
+        self.sweep_shift = r.u8()? & 0b111;
+        self.sweep_divider = r.u8()?;
+        self.sweep_reload = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playing_pulse(ones_complement: bool, period: u16) -> Pulse {
+        let mut pulse = Pulse::new(ones_complement);
+        pulse.length.set_enabled(true);
+        // 25% duty, constant volume 10
+        pulse.write_control(0b0101_1010);
+        pulse.write_timer_low(period as u8);
+        pulse.write_timer_high((period >> 8) as u8 | 0b0000_1000);
+        pulse
+    }
+
+    #[test]
+    fn test_duty_sequence() {
+        let mut pulse = playing_pulse(false, 0x100);
+        let mut levels = vec![];
+        for _ in 0..8 {
+            levels.push(pulse.output());
+            for _ in 0..=0x100 {
+                pulse.clock_timer();
+            }
+        }
+
+        assert_eq!(levels, vec![0, 10, 10, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_short_period_mutes() {
+        let mut pulse = playing_pulse(false, 7);
+        pulse.clock_timer();
+
+        assert_eq!(pulse.sequence_step, 1);
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut pulse1 = playing_pulse(true, 0x100);
+        let mut pulse2 = playing_pulse(false, 0x100);
+        // Enabled, period 0, negate, shift 1
+        pulse1.write_sweep(0b1000_1001);
+        pulse2.write_sweep(0b1000_1001);
+
+        pulse1.clock_half_frame();
+        pulse2.clock_half_frame();
+
+        assert_eq!(pulse1.timer_period, 0x7f);
+        assert_eq!(pulse2.timer_period, 0x80);
+    }
+
+    #[test]
+    fn test_sweep_overflow_mutes() {
+        let mut pulse = playing_pulse(false, 0x600);
+        pulse.clock_timer();
+        // Shift 0 adds the whole period, so only the shift 2 target fits in 11 bits
+        assert_eq!(pulse.output(), 0);
+        pulse.write_sweep(0b0000_0010);
+        assert_eq!(pulse.output(), 10);
+
+        // Shift 1 targets 0x900 even with the sweep disabled
+        pulse.write_sweep(0b0000_0001);
+        assert_eq!(pulse.output(), 0);
+    }
+}
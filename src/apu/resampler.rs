@@ -0,0 +1,189 @@
+use std::f64::consts::PI;
+
+// Fractional positions a step can start at between two output samples
+const PHASES: usize = 64;
+// Half the width of the band-limited step, in output samples
+const HALF_WIDTH: usize = 8;
+const TAPS: usize = 2 * HALF_WIDTH + 1;
+// Passband edge as a fraction of the output sample rate, just below Nyquist
+const CUTOFF: f64 = 0.45;
+const INTEGRATION_STEPS: usize = 32;
+
+/// Band-limited resampler from the CPU clock down to an audio sample rate.
+///
+/// Works like blargg's blip_buf: the input only matters where its amplitude
+/// changes, and every change is added to the output as a band-limited step,
+/// so nothing above the output Nyquist frequency aliases back in. The output
+/// buffer holds the differences between samples and is integrated on the way
+/// out, which delays the output by `HALF_WIDTH` samples.
+pub struct Resampler {
+    kernel: Vec<[f32; TAPS]>,
+    // Output samples per input clock
+    base_ratio: f64,
+    ratio: f64,
+    // Position of the next input clock in output samples, from the start of `deltas`
+    time: f64,
+    amplitude: f32,
+    deltas: Vec<f32>,
+    integrator: f32,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        let ratio = sample_rate / clock_rate;
+        Resampler {
+            kernel: step_kernel(),
+            base_ratio: ratio,
+            ratio,
+            time: 0.0,
+            amplitude: 0.0,
+            deltas: vec![0.0; TAPS],
+            integrator: 0.0,
+        }
+    }
+
+    /// Speeds the output up or down by a small factor, for dynamic rate control.
+    pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+        self.ratio = self.base_ratio * adjustment;
+    }
+
+    /// Adds the input amplitude for one clock.
+    pub fn push(&mut self, amplitude: f32) {
+        let delta = amplitude - self.amplitude;
+        if delta != 0.0 {
+            self.amplitude = amplitude;
+            self.add_step(delta);
+        }
+        self.time += self.ratio;
+    }
+
+    fn add_step(&mut self, delta: f32) {
+        let index = self.time as usize;
+        let phase = ((self.time - index as f64) * PHASES as f64) as usize;
+        if self.deltas.len() < index + TAPS {
+            self.deltas.resize(index + TAPS, 0.0);
+        }
+        for (tap, weight) in self.kernel[phase].iter().enumerate() {
+            self.deltas[index + tap] += delta * weight;
+        }
+    }
+
+    /// Moves every output sample that no longer changes into `out`.
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let available = self.time as usize;
+        if self.deltas.len() < available + TAPS {
+            self.deltas.resize(available + TAPS, 0.0);
+        }
+        for delta in self.deltas.drain(..available) {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+        self.time -= available as f64;
+    }
+}
+
+/// For every phase, the sample to sample differences of a step convolved with
+/// a Blackman windowed sinc.
+fn step_kernel() -> Vec<[f32; TAPS]> {
+    let impulse = |x: f64| {
+        if x.abs() >= HALF_WIDTH as f64 {
+            return 0.0;
+        }
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x)
+        };
+        let n = x / (2.0 * HALF_WIDTH as f64) + 0.5;
+        let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+        sinc * window
+    };
+
+    (0..PHASES)
+        .map(|phase| {
+            let offset = phase as f64 / PHASES as f64;
+            let mut taps = [0.0; TAPS];
+            for (tap, weight) in taps.iter_mut().enumerate() {
+                // Area of the impulse that falls between this output sample and the previous one
+                let start = tap as f64 - HALF_WIDTH as f64 - offset;
+                let step = 1.0 / INTEGRATION_STEPS as f64;
+                *weight = (0..INTEGRATION_STEPS)
+                    .map(|i| impulse(start + (i as f64 + 0.5) * step) * step)
+                    .sum::<f64>();
+            }
+            // Every step has to add up to exactly its height or the integrator drifts
+            let total: f64 = taps.iter().sum();
+            taps.map(|weight| (weight / total) as f32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK_RATE: f64 = 1_789_773.0;
+
+    fn resample(rate: f64, clocks: usize, input: impl Fn(usize) -> f32) -> Vec<f32> {
+        let mut resampler = Resampler::new(CLOCK_RATE, rate);
+        let mut out = vec![];
+        for clock in 0..clocks {
+            resampler.push(input(clock));
+        }
+        resampler.read_samples(&mut out);
+        out
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        (samples.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_output_rate() {
+        // One second of clocks, give or take the sample still in flight
+        let len = resample(48_000.0, CLOCK_RATE as usize, |_| 0.0).len();
+        assert!((47_999..=48_000).contains(&len));
+        let len = resample(44_100.0, CLOCK_RATE as usize, |_| 0.0).len();
+        assert!((44_099..=44_100).contains(&len));
+    }
+
+    #[test]
+    fn test_step_settles() {
+        let out = resample(
+            48_000.0,
+            20_000,
+            |clock| if clock < 1_000 { 0.0 } else { 0.5 },
+        );
+
+        assert!(out[..10].iter().all(|&s| s.abs() < 0.01));
+        let worst = out[40..]
+            .iter()
+            .map(|s| (s - 0.5).abs())
+            .fold(0.0, f32::max);
+        assert!(worst < 1e-3, "{}", worst);
+    }
+
+    #[test]
+    fn test_keeps_audible_and_removes_ultrasonic() {
+        // 1 kHz and 60 kHz squares, in CPU clocks per half period
+        let audible = resample(48_000.0, 200_000, |clock| ((clock / 895) % 2) as f32);
+        let ultrasonic = resample(48_000.0, 200_000, |clock| ((clock / 15) % 2) as f32);
+
+        assert!(rms(&audible[100..]) > 0.45);
+        assert!(rms(&ultrasonic[100..]) < 0.05);
+    }
+
+    #[test]
+    fn test_rate_adjustment() {
+        let mut resampler = Resampler::new(CLOCK_RATE, 48_000.0);
+        resampler.set_rate_adjustment(1.005);
+        for _ in 0..CLOCK_RATE as usize {
+            resampler.push(0.0);
+        }
+        let mut out = vec![];
+        resampler.read_samples(&mut out);
+
+        assert!((48_239..=48_240).contains(&out.len()));
+    }
+}
@@ -0,0 +1,38 @@
+use super::apu::CPU_CLOCK_RATE;
+use super::filter::FilterChain;
+use super::resampler::Resampler;
+
+/// Turns one amplitude per CPU cycle into filtered samples at an audio sample rate.
+pub struct SoundPipeline {
+    resampler: Resampler,
+    filters: FilterChain,
+    pub sample_rate: u32,
+}
+
+impl SoundPipeline {
+    pub fn new(sample_rate: u32) -> Self {
+        SoundPipeline {
+            resampler: Resampler::new(CPU_CLOCK_RATE, sample_rate as f64),
+            filters: FilterChain::new(sample_rate as f32),
+            sample_rate,
+        }
+    }
+
+    pub fn push(&mut self, amplitude: f32) {
+        self.resampler.push(amplitude);
+    }
+
+    /// See `Resampler::set_rate_adjustment`
+    pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+        self.resampler.set_rate_adjustment(adjustment);
+    }
+
+    /// Appends the samples ready so far to `out`.
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let start = out.len();
+        self.resampler.read_samples(out);
+        for sample in &mut out[start..] {
+            *sample = self.filters.process(*sample);
+        }
+    }
+}
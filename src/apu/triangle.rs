@@ -0,0 +1,146 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::length_counter::LengthCounter;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// Triangle wave channel https://www.nesdev.org/wiki/APU_Triangle
+#[derive(Default)]
+pub struct Triangle {
+    timer_period: u16,
+    timer: u16,
+    sequence_step: u8,
+    pub length: LengthCounter,
+    // Also halts the length counter
+    control: bool,
+    linear_counter: u8,
+    linear_reload_value: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle::default()
+    }
+
+    /// $4008: CRRR RRRR
+    pub fn write_linear_counter(&mut self, data: u8) {
+        self.control = data & 0b1000_0000 != 0;
+        self.length.set_halted(self.control);
+        self.linear_reload_value = data & 0b0111_1111;
+    }
+
+    /// $400A
+    pub fn write_timer_low(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x0700) | data as u16;
+    }
+
+    /// $400B: LLLL LTTT
+    pub fn write_timer_high(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | (((data & 0b111) as u16) << 8);
+        self.length.load(data >> 3);
+        self.linear_reload = true;
+    }
+
+    /// Clocked every CPU cycle, the sequencer only moves while both counters are non zero
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length.is_active() && self.linear_counter > 0 {
+                self.sequence_step = (self.sequence_step + 1) & 0b1_1111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// A silenced triangle holds its last level instead of dropping to 0.
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl Savestate for Triangle {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.u8(self.sequence_step);
+        Savestate::save(&self.length, w);
+        w.bool(self.control);
+        w.u8(self.linear_counter);
+        w.u8(self.linear_reload_value);
+        w.bool(self.linear_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        self.sequence_step = r.u8()? & 0b1_1111;
+        Savestate::load(&mut self.length, r)?;
+        self.control = r.bool()?;
+        self.linear_counter = r.u8()?;
+        self.linear_reload_value = r.u8()?;
+        self.linear_reload = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_linear_counter_gates_sequencer() {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write_linear_counter(2);
+        triangle.write_timer_high(0b0000_1000);
+
+        // Linear counter is still 0 until the next quarter frame
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 15);
+
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+    }
+
+    #[test]
+    fn test_control_keeps_reloading() {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write_linear_counter(0b1000_0001);
+        triangle.write_timer_high(0b0000_1000);
+
+        for _ in 0..4 {
+            triangle.clock_quarter_frame();
+        }
+        assert_eq!(triangle.linear_counter, 1);
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+
+const BITS_PER_SAMPLE: u16 = 16;
+const HEADER_SIZE: usize = 44;
+
+/// Encodes mono samples in -1.0..=1.0 as a 16 bit PCM WAV file.
+pub fn encode(sample_rate: u32, samples: &[f32]) -> Vec<u8> {
+    let data_size = (samples.len() * 2) as u32;
+    let block_align = BITS_PER_SAMPLE / 8;
+    let mut wav = Vec::with_capacity(HEADER_SIZE + data_size as usize);
+
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, 1 channel
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+pub fn write(path: &Path, sample_rate: u32, samples: &[f32]) -> std::io::Result<()> {
+    fs::write(path, encode(sample_rate, samples))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let wav = encode(48_000, &[0.0, 1.0, -2.0]);
+
+        assert_eq!(wav.len(), HEADER_SIZE + 6);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[4..8], &42u32.to_le_bytes());
+        assert_eq!(&wav[24..28], &48_000u32.to_le_bytes());
+        assert_eq!(&wav[28..32], &96_000u32.to_le_bytes());
+        assert_eq!(&wav[40..44], &6u32.to_le_bytes());
+        assert_eq!(&wav[44..], &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{bus::Bus, error::EmuError};
+
+/// File the battery backed PRG-RAM is kept in, next to the ROM: `game.nes` saves to `game.sav`.
+pub fn sav_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// Keeps the PRG-RAM of battery backed cartridges in a file between runs.
+/// Remembers what was last written, so flushing an unchanged RAM is free.
+pub struct BatterySave {
+    path: PathBuf,
+    saved: Vec<u8>,
+}
+
+impl BatterySave {
+    /// Loads `path` into PRG-RAM if the file exists. Returns None when the
+    /// cartridge has no battery or no PRG-RAM to keep.
+    pub fn open(bus: &mut Bus, path: PathBuf) -> Result<Option<Self>, EmuError> {
+        if !bus.battery {
+            return Ok(None);
+        }
+        let mut mapper = bus.mapper.borrow_mut();
+        let ram = mapper.prg_ram_mut();
+        if ram.is_empty() {
+            return Ok(None);
+        }
+
+        match fs::read(&path) {
+            Ok(data) if data.len() == ram.len() => ram.copy_from_slice(&data),
+            Ok(data) => {
+                return Err(EmuError::BatterySaveSizeMismatch {
+                    expected: ram.len(),
+                    actual: data.len(),
+                });
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(Some(BatterySave {
+            path,
+            saved: ram.to_vec(),
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes PRG-RAM out if it changed since the last write. The data goes to
+    /// a temporary file first, so a crash halfway keeps the old save intact.
+    pub fn flush(&mut self, bus: &Bus) -> Result<(), EmuError> {
+        let mapper = bus.mapper.borrow();
+        let ram = mapper.prg_ram();
+        if ram == self.saved.as_slice() {
+            return Ok(());
+        }
+
+        let temp_path = self.path.with_extension("sav.tmp");
+        fs::write(&temp_path, ram)?;
+        fs::rename(&temp_path, &self.path)?;
+        self.saved = ram.to_vec();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::process;
+
+    use crate::rom::Rom;
+
+    fn battery_bus() -> Bus {
+        let mut rom = Rom::from_test_code(vec![]);
+        rom.battery = true;
+        Bus::new(rom).unwrap()
+    }
+
+    fn temp_sav(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("nes_battery_{}_{}.sav", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_sav("round_trip");
+        let mut bus = battery_bus();
+        let mut save = BatterySave::open(&mut bus, path.clone()).unwrap().unwrap();
+        assert!(!path.exists());
+
+        bus.mapper.borrow_mut().write_prg(0x6010, 0x42);
+        save.flush(&bus).unwrap();
+
+        let mut restarted = battery_bus();
+        BatterySave::open(&mut restarted, path.clone()).unwrap();
+        assert_eq!(restarted.mapper.borrow().read_prg(0x6010), 0x42);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unchanged_ram_is_not_written() {
+        let path = temp_sav("unchanged");
+        let mut bus = battery_bus();
+        let mut save = BatterySave::open(&mut bus, path.clone()).unwrap().unwrap();
+
+        save.flush(&bus).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_without_battery() {
+        let mut bus = Bus::new(Rom::from_test_code(vec![])).unwrap();
+        let save = BatterySave::open(&mut bus, temp_sav("no_battery")).unwrap();
+        assert!(save.is_none());
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let path = temp_sav("mismatch");
+        fs::write(&path, [0; 16]).unwrap();
+        let mut bus = battery_bus();
+
+        let result = BatterySave::open(&mut bus, path.clone());
+        assert_eq!(
+            result.err(),
+            Some(EmuError::BatterySaveSizeMismatch {
+                expected: 0x2000,
+                actual: 16
+            })
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
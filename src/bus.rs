@@ -1,45 +1,210 @@
-use crate::{cpu::memory::Mem, rom::Rom};
+use crate::{
+    apu::apu::Apu,
+    cpu::memory::Mem,
+    error::EmuError,
+    joypad::Joypad,
+    mapper::mapper::{self, SharedMapper},
+    ppu::ppu::NesPPU,
+    rom::Rom,
+    savestate::{Savestate, StateReader, StateWriter},
+};
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD1: u16 = 0x4016;
+const JOYPAD2: u16 = 0x4017;
+// Shares the address with JOYPAD2, which only answers reads
+const APU_FRAME_COUNTER: u16 = 0x4017;
+const CARTRIDGE: u16 = 0x6000;
+const CARTRIDGE_END: u16 = 0xFFFF;
+
+/// A CPU bus access, recorded for debugger watchpoints
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Access {
+    pub addr: u16,
+    pub write: bool,
+}
 
 pub struct Bus {
     cpu_vram: [u8; 2048],
-    rom: Rom,
+    pub mapper: SharedMapper,
+    pub ppu: NesPPU,
+    pub apu: Apu,
+    pub joypad1: Joypad,
+    pub joypad2: Joypad,
+    // Save states only load into the ROM they were made with
+    pub rom_hash: u64,
+    // PRG-RAM is kept in a .sav file between runs
+    pub battery: bool,
+
+    pub cycles: usize,
+    // Set when the PPU finishes a frame, until the frontend picks it up
+    frame_complete: bool,
+    // Set by a write to $4014, until the CPU picks up the stall
+    oam_dma_pending: bool,
+    // Cycles stolen by DMC sample fetches, until the CPU picks up the stall
+    dmc_stall: usize,
+    // Every read and write goes in here while a debugger watches memory
+    pub access_log: Option<Vec<Access>>,
 }
 
 impl Bus {
-    pub fn new(rom: Rom) -> Self {
-        Bus {
+    pub fn new(rom: Rom) -> Result<Self, EmuError> {
+        let rom_hash = rom.hash();
+        let battery = rom.battery;
+        let mapper = mapper::from_rom(rom)?;
+        let ppu = NesPPU::new(mapper.clone());
+
+        Ok(Bus {
             cpu_vram: [0; 2048],
-            rom,
+            mapper,
+            ppu,
+            apu: Apu::new(),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            rom_hash,
+            battery,
+            cycles: 0,
+            frame_complete: false,
+            oam_dma_pending: false,
+            dmc_stall: 0,
+            access_log: None,
+        })
+    }
+
+    /// Called by the CPU after every instruction with the number of cycles it took,
+    /// so the rest of the system can catch up.
+    pub fn tick(&mut self, cycles: usize) {
+        self.cycles += cycles;
+        // The PPU runs 3 dots per CPU cycle
+        if self.ppu.tick(cycles * 3) {
+            self.frame_complete = true;
+        }
+        for _ in 0..cycles {
+            self.apu.tick(1);
+            // The DMC fetches its sample bytes from the cartridge, always in
+            // $8000-$FFFF. It skips the access log, these are not CPU reads.
+            if let Some(addr) = self.apu.dmc.dma_request() {
+                let data = self.mapper.borrow().read_prg(addr);
+                self.apu.dmc.load_sample_byte(data);
+                self.dmc_stall += 4;
+            }
+        }
+    }
+
+    /// Copies a 256 byte CPU page into OAM, starting at the current OAMADDR.
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        for offset in 0..=0xff {
+            let data = self.mem_read(start + offset);
+            self.ppu.write_to_oam_data(data);
         }
+        self.oam_dma_pending = true;
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            // Mirror is needed
-            addr = addr % 0x4000;
+    /// Number of CPU cycles the DMAs started since the last call keep the CPU off the bus.
+    pub fn take_dma_stall(&mut self) -> usize {
+        let mut stall = std::mem::take(&mut self.dmc_stall);
+        if std::mem::take(&mut self.oam_dma_pending) {
+            // 256 reads and 256 writes, one cycle to halt the CPU and
+            // another one to align with a read cycle when it lands on an odd cycle
+            stall += 513 + self.cycles % 2;
         }
-        self.rom.prg_rom[addr as usize]
+        stall
+    }
+
+    /// Returns and clears whether a new frame is ready in `ppu.frame`.
+    pub fn poll_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    /// Reads memory without the side effects a CPU read may have, used by debugging tools.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b00000111_11111111;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.ppu.peek_register(mirror_down_addr)
+            }
+            APU_STATUS => self.apu.peek_status(),
+            JOYPAD1 => self.joypad1.peek(),
+            JOYPAD2 => self.joypad2.peek(),
+            CARTRIDGE..=CARTRIDGE_END => self.mapper.borrow().peek_prg(addr),
+            _ => 0,
+        }
+    }
+
+    pub fn poll_nmi_status(&mut self) -> bool {
+        self.ppu.poll_nmi_interrupt()
+    }
+
+    /// Hands out a fault raised by the cartridge since the last call.
+    pub fn take_fault(&mut self) -> Option<EmuError> {
+        self.mapper.borrow_mut().take_fault()
+    }
+
+    /// Level of the shared IRQ line.
+    pub fn poll_irq_status(&self) -> bool {
+        self.mapper.borrow().irq_pending() || self.apu.irq_pending()
+    }
+}
+
+impl Savestate for Bus {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.cpu_vram);
+        self.mapper.borrow().save(w);
+        self.ppu.save(w);
+        self.apu.save(w);
+        self.joypad1.save(w);
+        self.joypad2.save(w);
+        w.usize(self.cycles);
+        w.bool(self.frame_complete);
+        w.bool(self.oam_dma_pending);
+        w.usize(self.dmc_stall);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        r.bytes(&mut self.cpu_vram)?;
+        self.mapper.borrow_mut().load(r)?;
+        self.ppu.load(r)?;
+        self.apu.load(r)?;
+        self.joypad1.load(r)?;
+        self.joypad2.load(r)?;
+        self.cycles = r.usize()?;
+        self.frame_complete = r.bool()?;
+        self.oam_dma_pending = r.bool()?;
+        self.dmc_stall = r.usize()?;
+        Ok(())
     }
 }
 
 impl Mem for Bus {
-    fn mem_read(&self, addr: u16) -> u8 {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        if let Some(log) = &mut self.access_log {
+            log.push(Access { addr, write: false });
+        }
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirror_down_addr as usize]
             }
             PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirron_down_addr = addr & 0b00100000_00000111;
-                todo!("Support PPU")
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.ppu.read_register(mirror_down_addr)
             }
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
+            APU_STATUS => self.apu.read_status(),
+            JOYPAD1 => self.joypad1.read(),
+            JOYPAD2 => self.joypad2.read(),
+            CARTRIDGE..=CARTRIDGE_END => self.mapper.borrow().read_prg(addr),
 
             _ => {
                 println!("Ignoring mem access at {}", addr);
@@ -49,18 +214,28 @@ impl Mem for Bus {
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
+        if let Some(log) = &mut self.access_log {
+            log.push(Access { addr, write: true });
+        }
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirror_down_addr as usize] = data;
             }
             PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirron_down_addr = addr & 0b00100000_00000111;
-                todo!("Support PPU")
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.ppu.write_register(mirror_down_addr, data);
+            }
+            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS | APU_FRAME_COUNTER => {
+                self.apu.write_register(addr, data)
             }
-            0x8000..=0xFFFF => {
-                panic!("Attempt to write to Cartridge ROM space")
+            OAM_DMA => self.oam_dma(data),
+            // The strobe line is shared by both ports
+            JOYPAD1 => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
             }
+            CARTRIDGE..=CARTRIDGE_END => self.mapper.borrow_mut().write_prg(addr, data),
             _ => {
                 println!("Ignoring mem write-access at {}", addr);
             }
@@ -71,13 +246,143 @@ impl Mem for Bus {
 #[cfg(test)]
 impl Bus {
     pub fn test_new() -> Self {
-        Bus {
-            cpu_vram: [0; 2048],
-            rom: Rom::from_test_code(vec![]),
-        }
+        Bus::new(Rom::from_test_code(vec![])).unwrap()
     }
 
     pub fn load_rom(&mut self, rom: Rom) {
-        self.rom = rom;
+        self.rom_hash = rom.hash();
+        self.battery = rom.battery;
+        self.mapper = mapper::from_rom(rom).unwrap();
+        self.ppu = NesPPU::new(self.mapper.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ppu_registers_are_mirrored() {
+        let mut bus = Bus::test_new();
+
+        // PPUADDR through $3FFE, PPUDATA through $2007
+        bus.mem_write(0x3ffe, 0x23);
+        bus.mem_write(0x2006, 0x05);
+        bus.mem_write(0x2007, 0x66);
+
+        assert_eq!(bus.ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_peek_without_prg_ram() {
+        let mut rom = Rom::from_test_code(vec![0xea]);
+        rom.prg_ram_size = 0;
+        let bus = Bus::new(rom).unwrap();
+
+        assert!(bus.mapper.borrow().prg_ram().is_empty());
+        assert_eq!(bus.peek(0x6000), 0);
+        assert_eq!(bus.peek(0x8000), 0xea);
+    }
+
+    #[test]
+    fn test_tick_clocks_ppu_three_times_per_cycle() {
+        let mut bus = Bus::test_new();
+
+        bus.tick(7);
+
+        assert_eq!(bus.cycles, 7);
+        assert_eq!(bus.ppu.cycles, 21);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_a_page() {
+        let mut bus = Bus::test_new();
+        for i in 0..=0xffu16 {
+            bus.mem_write(0x0200 + i, i as u8);
+        }
+        bus.ppu.write_to_oam_addr(0x10);
+
+        bus.mem_write(0x4014, 0x02);
+
+        // The copy starts at OAMADDR and wraps around
+        assert_eq!(bus.ppu.oam_data[0x10], 0x00);
+        assert_eq!(bus.ppu.oam_data[0xff], 0xef);
+        assert_eq!(bus.ppu.oam_data[0x00], 0xf0);
+        assert_eq!(bus.ppu.oam_addr, 0x10);
+    }
+
+    #[test]
+    fn test_oam_dma_stall_depends_on_parity() {
+        let mut bus = Bus::test_new();
+        assert_eq!(bus.take_dma_stall(), 0);
+
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.take_dma_stall(), 513);
+        assert_eq!(bus.take_dma_stall(), 0);
+
+        bus.tick(1);
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.take_dma_stall(), 514);
+    }
+
+    #[test]
+    fn test_joypads_are_read_per_player() {
+        let mut bus = Bus::test_new();
+        bus.joypad1
+            .set_button_pressed_status(crate::joypad::JoypadButton::BUTTON_A, true);
+        bus.joypad2
+            .set_button_pressed_status(crate::joypad::JoypadButton::BUTTON_B, true);
+
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        assert_eq!(bus.mem_read(0x4016), 0x41);
+        assert_eq!(bus.mem_read(0x4016), 0x40);
+        assert_eq!(bus.mem_read(0x4017), 0x40);
+        assert_eq!(bus.mem_read(0x4017), 0x41);
+    }
+
+    #[test]
+    fn test_apu_frame_irq_reaches_irq_line() {
+        let mut bus = Bus::test_new();
+        bus.tick(29829);
+        assert!(bus.poll_irq_status());
+
+        bus.mem_read(0x4015);
+        assert!(!bus.poll_irq_status());
+
+        // $4017 writes go to the frame counter, not to the second joypad
+        bus.mem_write(0x4017, 0x40);
+        bus.tick(29829);
+        assert!(!bus.poll_irq_status());
+    }
+
+    #[test]
+    fn test_dmc_fetches_through_bus_and_stalls() {
+        let mut bus = Bus::test_new();
+
+        // $C040, 1 byte
+        bus.mem_write(0x4012, 0x01);
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0b0001_0000);
+        bus.tick(1);
+
+        assert_eq!(bus.take_dma_stall(), 4);
+        assert_eq!(bus.take_dma_stall(), 0);
+        assert_eq!(bus.mem_read(0x4015) & 0b0001_0000, 0);
+    }
+
+    #[test]
+    fn test_dmc_fetch_is_not_logged() {
+        let mut bus = Bus::test_new();
+        bus.mem_write(0x4012, 0x01);
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0b0001_0000);
+
+        bus.access_log = Some(vec![]);
+        bus.tick(1);
+
+        assert_eq!(bus.take_dma_stall(), 4);
+        assert_eq!(bus.access_log, Some(vec![]));
     }
 }
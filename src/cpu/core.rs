@@ -1,39 +1,44 @@
 use crate::cpu::cpu::CPU;
 use crate::cpu::opcodes;
 
+use crate::error::EmuError;
+
 use super::{cpu::AddressingMode, flags::StatusFlags, memory::Mem};
 
 impl CPU {
-    pub fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
+    /// Resolves the effective address of the operand at the program counter.
+    /// The flag reports whether indexing crossed a page boundary, which costs
+    /// an extra cycle for the read instructions.
+    pub fn get_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
         match mode {
-            AddressingMode::Immediate => self.program_counter,
+            AddressingMode::Immediate => (self.program_counter, false),
 
-            AddressingMode::Implied => self.program_counter,
+            AddressingMode::Implied => (self.program_counter, false),
 
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+            AddressingMode::ZeroPage => (self.mem_read(self.program_counter) as u16, false),
 
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            AddressingMode::Absolute => (self.mem_read_u16(self.program_counter), false),
 
             AddressingMode::ZeroPage_X => {
                 let pos = self.mem_read(self.program_counter);
                 let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
+                (addr, false)
             }
             AddressingMode::ZeroPage_Y => {
                 let pos = self.mem_read(self.program_counter);
                 let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
+                (addr, false)
             }
 
             AddressingMode::Absolute_X => {
                 let base = self.mem_read_u16(self.program_counter);
                 let addr = base.wrapping_add(self.register_x as u16);
-                addr
+                (addr, page_cross(base, addr))
             }
             AddressingMode::Absolute_Y => {
                 let base = self.mem_read_u16(self.program_counter);
                 let addr = base.wrapping_add(self.register_y as u16);
-                addr
+                (addr, page_cross(base, addr))
             }
 
             AddressingMode::Indirect => {
@@ -46,7 +51,7 @@ impl CPU {
                     addr + 1
                 });
 
-                (hi as u16) << 8 | (lo as u16)
+                ((hi as u16) << 8 | (lo as u16), false)
             }
 
             AddressingMode::Indirect_X => {
@@ -55,7 +60,7 @@ impl CPU {
                 let ptr: u8 = (base as u8).wrapping_add(self.register_x);
                 let lo = self.mem_read(ptr as u16);
                 let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
+                ((hi as u16) << 8 | (lo as u16), false)
             }
             AddressingMode::Indirect_Y => {
                 let base = self.mem_read(self.program_counter);
@@ -64,7 +69,7 @@ impl CPU {
                 let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
                 let deref = deref_base.wrapping_add(self.register_y as u16);
-                deref
+                (deref, page_cross(deref_base, deref))
             }
 
             AddressingMode::NoneAddressing | _ => {
@@ -74,7 +79,10 @@ impl CPU {
     }
 
     pub fn get_mode_return_value(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
+        let (addr, page_crossed) = self.get_operand_address(mode);
+        if page_crossed {
+            self.cycles += 1;
+        }
         let value = self.mem_read(addr);
         return value;
     }
@@ -82,124 +90,208 @@ impl CPU {
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
-        self.status = StatusFlags::UNUSED | StatusFlags::BREAK;
+        // IRQs stay masked until the program is ready for them
+        self.status = StatusFlags::UNUSED | StatusFlags::BREAK | StatusFlags::INTERRUPT;
         self.sp = 0xff;
 
         self.program_counter = self.mem_read_u16(0xFFFC);
+
+        // The reset sequence takes 7 cycles before the first instruction
+        self.cycles = 7;
+        self.bus.tick(7);
     }
 
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
+    pub fn run(&mut self) -> Result<(), EmuError> {
+        self.run_with_callback(|_| {})
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    /// Runs until the CPU halts or faults, calling `callback` before every instruction.
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<(), EmuError>
     where
         F: FnMut(&mut CPU),
     {
         loop {
             callback(self);
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let original_program_counter = self.program_counter;
+            if !self.step()? {
+                return Ok(());
+            }
+        }
+    }
 
-            let opcode = opcodes::CODES_MAP
-                .get(&code)
-                .expect(&format!("OpCode {:x} is not recognized", code));
+    /// Services a pending interrupt or executes a single instruction.
+    /// Returns false once the CPU has halted.
+    pub fn step(&mut self) -> Result<bool, EmuError> {
+        if self.poll_interrupts() {
+            return Ok(true);
+        }
 
-            match code {
-                0xA9 | 0xA5 | 0xAD | 0xb5 | 0xbd | 0xb9 | 0xa1 | 0xb1 => self.lda(&opcode.mode),
+        let cycles_before = self.cycles;
+        let code = self.mem_read(self.program_counter);
+        self.program_counter += 1;
+        let original_program_counter = self.program_counter;
 
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => self.ldx(&opcode.mode),
+        let opcode = opcodes::CODES_MAP
+            .get(&code)
+            .ok_or(EmuError::UnknownOpcode {
+                opcode: code,
+                pc: original_program_counter - 1,
+            })?;
 
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => self.ldy(&opcode.mode),
+        match code {
+            0xA9 | 0xA5 | 0xAD | 0xb5 | 0xbd | 0xb9 | 0xa1 | 0xb1 => self.lda(&opcode.mode),
 
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => self.adc(&opcode.mode),
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => self.ldx(&opcode.mode),
 
-                0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => self.sbc(&opcode.mode),
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => self.ldy(&opcode.mode),
 
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => self.cmp(&opcode.mode),
-                0xe0 | 0xe4 | 0xec => self.cpx(&opcode.mode),
-                0xc0 | 0xc4 | 0xcc => self.cpy(&opcode.mode),
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => self.adc(&opcode.mode),
 
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => self.sta(&opcode.mode),
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => self.sbc(&opcode.mode),
 
-                0x86 | 0x96 | 0x8e => self.stx(&opcode.mode),
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => self.cmp(&opcode.mode),
+            0xe0 | 0xe4 | 0xec => self.cpx(&opcode.mode),
+            0xc0 | 0xc4 | 0xcc => self.cpy(&opcode.mode),
 
-                0x84 | 0x94 | 0x8c => self.sty(&opcode.mode),
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => self.sta(&opcode.mode),
 
-                0xAA => self.tax(),
-                0xa8 => self.tay(),
-                0x8a => self.txa(),
-                0x98 => self.tya(),
+            0x86 | 0x96 | 0x8e => self.stx(&opcode.mode),
 
-                0xe8 => self.inx(),
+            0x84 | 0x94 | 0x8c => self.sty(&opcode.mode),
 
-                0xc8 => self.iny(),
+            0xAA => self.tax(),
+            0xa8 => self.tay(),
+            0x8a => self.txa(),
+            0x98 => self.tya(),
 
-                0x88 => self.dey(),
+            0xe8 => self.inx(),
 
-                0xca => self.dex(),
+            0xc8 => self.iny(),
 
-                0xe6 | 0xf6 | 0xee | 0xfe => self.inc(&opcode.mode),
+            0x88 => self.dey(),
 
-                0xc6 | 0xd6 | 0xce | 0xde => self.dec(&opcode.mode),
+            0xca => self.dex(),
 
-                0x4c | 0x6c => self.jmp(&opcode.mode),
-                0x20 => self.jsr(&opcode.mode),
-                0x60 => self.rts(),
+            0xe6 | 0xf6 | 0xee | 0xfe => self.inc(&opcode.mode),
 
-                0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => self.and(&opcode.mode),
+            0xc6 | 0xd6 | 0xce | 0xde => self.dec(&opcode.mode),
 
-                0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => self.eor(&opcode.mode),
+            0x4c | 0x6c => self.jmp(&opcode.mode),
+            0x20 => self.jsr(&opcode.mode),
+            0x60 => self.rts(),
 
-                0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => self.ora(&opcode.mode),
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => self.and(&opcode.mode),
 
-                0x024 | 0x2c => self.bit(&opcode.mode),
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => self.eor(&opcode.mode),
 
-                0x06 | 0x16 | 0x0e | 0x1e | 0x0a => self.asl(&opcode.mode),
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => self.ora(&opcode.mode),
 
-                0x4a | 0x46 | 0x56 | 0x4e | 0x5e => self.lsr(&opcode.mode),
+            0x024 | 0x2c => self.bit(&opcode.mode),
 
-                0x26 | 0x36 | 0x2e | 0x3e | 0x2a => self.rol(&opcode.mode),
+            0x06 | 0x16 | 0x0e | 0x1e | 0x0a => self.asl(&opcode.mode),
 
-                0x6a | 0x66 | 0x76 | 0x6e | 0x7e => self.ror(&opcode.mode),
+            0x4a | 0x46 | 0x56 | 0x4e | 0x5e => self.lsr(&opcode.mode),
 
-                0x18 => self.clear_carry_flag(),
-                0xD8 => self.clear_decimal_flag(),
-                0x58 => self.clear_interrupt_disable_flag(),
-                0xB8 => self.clear_overflow_flag(),
-                0x38 => self.set_carry_flag(),
-                0xF8 => self.set_decimal_flag(),
-                0x78 => self.set_interrupt_disable_flag(),
+            0x26 | 0x36 | 0x2e | 0x3e | 0x2a => self.rol(&opcode.mode),
 
-                // Branches
-                0x90 => self.bcc(),
-                0xd0 => self.bne(),
-                0x70 => self.bvs(),
-                0x50 => self.bvc(),
-                0x10 => self.bpl(),
-                0x30 => self.bmi(),
-                0xf0 => self.beq(),
-                0xb0 => self.bcs(),
+            0x6a | 0x66 | 0x76 | 0x6e | 0x7e => self.ror(&opcode.mode),
 
-                // Stack
-                0x48 => self.pha(),
-                0x08 => self.php(),
-                0x68 => self.pla(),
-                0x28 => self.plp(),
-                0x9a => self.txs(),
-                0xba => self.tsx(),
+            0x18 => self.clear_carry_flag(),
+            0xD8 => self.clear_decimal_flag(),
+            0x58 => self.clear_interrupt_disable_flag(),
+            0xB8 => self.clear_overflow_flag(),
+            0x38 => self.set_carry_flag(),
+            0xF8 => self.set_decimal_flag(),
+            0x78 => self.set_interrupt_disable_flag(),
 
-                0x00 => return,
+            // Branches
+            0x90 => self.bcc(),
+            0xd0 => self.bne(),
+            0x70 => self.bvs(),
+            0x50 => self.bvc(),
+            0x10 => self.bpl(),
+            0x30 => self.bmi(),
+            0xf0 => self.beq(),
+            0xb0 => self.bcs(),
 
-                0xea => {}
+            // Stack
+            0x48 => self.pha(),
+            0x08 => self.php(),
+            0x68 => self.pla(),
+            0x28 => self.plp(),
+            0x9a => self.txs(),
+            0xba => self.tsx(),
 
-                _ => todo!(),
+            0x00 => {
+                if self.halt_on_brk {
+                    return Ok(false);
+                }
+                self.brk();
             }
+            0x40 => self.rti(),
+
+            0xea => {}
+
+            // Unofficial
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa | 0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 | 0x04
+            | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c | 0x3c | 0x5c
+            | 0x7c | 0xdc | 0xfc => self.nop_read(&opcode.mode),
 
-            if original_program_counter == self.program_counter {
-                self.program_counter += (opcode.bytes - 1) as u16;
+            // JAM freezes the CPU until reset
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                return Ok(false);
             }
+
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => self.lax(&opcode.mode),
+            0x87 | 0x97 | 0x8f | 0x83 => self.sax(&opcode.mode),
+            0xeb => self.sbc(&opcode.mode),
+            0xc7 | 0xd7 | 0xcf | 0xdf | 0xdb | 0xc3 | 0xd3 => self.dcp(&opcode.mode),
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => self.isb(&opcode.mode),
+            0x07 | 0x17 | 0x0f | 0x1f | 0x1b | 0x03 | 0x13 => self.slo(&opcode.mode),
+            0x27 | 0x37 | 0x2f | 0x3f | 0x3b | 0x23 | 0x33 => self.rla(&opcode.mode),
+            0x47 | 0x57 | 0x4f | 0x5f | 0x5b | 0x43 | 0x53 => self.sre(&opcode.mode),
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => self.rra(&opcode.mode),
+            0x0b | 0x2b => self.anc(&opcode.mode),
+            0x4b => self.alr(&opcode.mode),
+            0x6b => self.arr(&opcode.mode),
+            0xcb => self.axs(&opcode.mode),
+            0xbb => self.las(&opcode.mode),
+
+            // Unstable
+            0x8b | 0xab | 0x93 | 0x9f | 0x9b | 0x9c | 0x9e if self.trap_unstable_opcodes => {
+                return Err(EmuError::UnstableOpcode {
+                    opcode: code,
+                    pc: original_program_counter - 1,
+                });
+            }
+            0x8b => self.xaa(&opcode.mode),
+            0xab => self.lxa(&opcode.mode),
+            0x93 | 0x9f => self.ahx(&opcode.mode),
+            0x9b => self.tas(&opcode.mode),
+            0x9c => self.shy(&opcode.mode),
+            0x9e => self.shx(&opcode.mode),
+        }
+
+        if original_program_counter == self.program_counter {
+            self.program_counter += (opcode.bytes - 1) as u16;
+        }
+
+        self.cycles += opcode.cycles as usize;
+        self.bus.tick(self.cycles - cycles_before);
+
+        // DMA takes the bus right after the instruction that started it
+        let stall = self.bus.take_dma_stall();
+        if stall > 0 {
+            self.cycles += stall;
+            self.bus.tick(stall);
+        }
+
+        match self.bus.take_fault() {
+            Some(fault) => Err(fault),
+            None => Ok(true),
         }
     }
 }
+
+pub fn page_cross(addr1: u16, addr2: u16) -> bool {
+    addr1 & 0xFF00 != addr2 & 0xFF00
+}
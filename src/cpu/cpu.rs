@@ -1,4 +1,8 @@
-use crate::bus::Bus;
+use crate::{
+    bus::Bus,
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
 
 use super::{flags::StatusFlags, memory::Mem};
 
@@ -27,7 +31,16 @@ pub struct CPU {
     pub status: StatusFlags,
     pub program_counter: u16,
     pub sp: u8,
+    pub cycles: usize,
     pub bus: Bus,
+
+    pub nmi_pending: bool,
+    pub irq_line: bool,
+    // Stop `run` on BRK instead of taking the software interrupt, used by tests
+    pub halt_on_brk: bool,
+    // Stop on XAA/LXA/AHX/TAS/SHX/SHY with `EmuError::UnstableOpcode` instead of
+    // emulating their usual behaviour
+    pub trap_unstable_opcodes: bool,
 }
 
 impl CPU {
@@ -39,13 +52,46 @@ impl CPU {
             status: StatusFlags::UNUSED | StatusFlags::BREAK,
             program_counter: 0x8000,
             sp: 0xff,
+            cycles: 0,
             bus,
+            nmi_pending: false,
+            irq_line: false,
+            halt_on_brk: false,
+            trap_unstable_opcodes: false,
         }
     }
 }
 
+impl Savestate for CPU {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.register_a);
+        w.u8(self.register_x);
+        w.u8(self.register_y);
+        w.u8(self.status.bits());
+        w.u16(self.program_counter);
+        w.u8(self.sp);
+        w.usize(self.cycles);
+        w.bool(self.nmi_pending);
+        w.bool(self.irq_line);
+        self.bus.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.register_a = r.u8()?;
+        self.register_x = r.u8()?;
+        self.register_y = r.u8()?;
+        self.status = StatusFlags::from_bits_retain(r.u8()?);
+        self.program_counter = r.u16()?;
+        self.sp = r.u8()?;
+        self.cycles = r.usize()?;
+        self.nmi_pending = r.bool()?;
+        self.irq_line = r.bool()?;
+        self.bus.load(r)
+    }
+}
+
 impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
+    fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
 
@@ -65,7 +111,9 @@ impl Mem for CPU {
 #[cfg(test)]
 impl CPU {
     pub fn test_new() -> Self {
-        CPU::new(Bus::test_new())
+        let mut cpu = CPU::new(Bus::test_new());
+        cpu.halt_on_brk = true;
+        cpu
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
@@ -78,7 +126,7 @@ impl CPU {
     pub fn load_and_run(&mut self, program: Vec<u8>) {
         self.load(program);
         self.reset();
-        self.run()
+        self.run().unwrap()
     }
 }
 
@@ -92,4 +140,36 @@ mod test {
         cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
         assert_eq!(cpu.register_x, 0xc1)
     }
+
+    #[test]
+    fn test_rom_write_is_reported() {
+        let mut cpu = CPU::test_new();
+        // STA $8000 on NROM
+        cpu.load(vec![0xa9, 0x42, 0x8d, 0x00, 0x80, 0x00]);
+        cpu.reset();
+
+        assert_eq!(
+            cpu.run(),
+            Err(crate::error::EmuError::UnhandledRomWrite {
+                addr: 0x8000,
+                data: 0x42
+            })
+        );
+        assert_eq!(cpu.program_counter, 0x8005);
+    }
+
+    #[test]
+    fn test_oam_dma_stalls_the_cpu() {
+        let mut cpu = CPU::test_new();
+        // LDA #$02; STA $4014
+        cpu.load(vec![0xa9, 0x02, 0x8d, 0x14, 0x40, 0x00]);
+        cpu.reset();
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        // The STA ends on cycle 13, so DMA needs an extra alignment cycle
+        assert_eq!(cpu.cycles, 7 + 2 + 4 + 514);
+        assert_eq!(cpu.bus.cycles, cpu.cycles);
+    }
 }
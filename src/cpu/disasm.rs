@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{
+    cpu::AddressingMode,
+    opcodes::{self, OpCode},
+};
+
+const JMP_ABSOLUTE: u8 = 0x4c;
+const JSR: u8 = 0x20;
+const BYTES_PER_DATA_LINE: usize = 8;
+
+/// The interrupt vectors at the top of the address space, in address order
+pub const VECTORS: [(u16, &str); 3] = [(0xFFFA, "NMI"), (0xFFFC, "RESET"), (0xFFFE, "IRQ")];
+
+/// Names for addresses, shown as line labels and in place of operands
+pub type Labels = BTreeMap<u16, String>;
+
+/// A single decoded instruction.
+pub struct Instruction {
+    pub addr: u16,
+    pub opcode: &'static OpCode,
+    // The operand byte, or the little endian operand word, 0 when there is none
+    pub operand: u16,
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`, None for opcodes the CPU doesn't know.
+    pub fn decode<F: Fn(u16) -> u8>(addr: u16, read: F) -> Option<Self> {
+        let opcode = *opcodes::CODES_MAP.get(&read(addr))?;
+        let operand = match opcode.bytes {
+            2 => read(addr.wrapping_add(1)) as u16,
+            3 => u16::from_le_bytes([read(addr.wrapping_add(1)), read(addr.wrapping_add(2))]),
+            _ => 0,
+        };
+        Some(Instruction {
+            addr,
+            opcode,
+            operand,
+        })
+    }
+
+    /// Length in bytes, opcode included
+    pub fn size(&self) -> u16 {
+        self.opcode.bytes as u16
+    }
+
+    /// Where a branch, JMP or JSR goes. JMP ($nnnn) only knows at run time.
+    pub fn target(&self) -> Option<u16> {
+        match self.opcode.mode {
+            // Relative branches, the offset counts from the next instruction
+            AddressingMode::NoneAddressing => Some(
+                self.addr
+                    .wrapping_add(2)
+                    .wrapping_add(self.operand as u8 as i8 as u16),
+            ),
+            _ if self.opcode.code == JMP_ABSOLUTE || self.opcode.code == JSR => Some(self.operand),
+            _ => None,
+        }
+    }
+
+    /// Whether execution can go on with the instruction after this one
+    pub fn falls_through(&self) -> bool {
+        !matches!(self.opcode.mnemonic, "JMP" | "RTS" | "RTI" | "BRK" | "*JAM")
+    }
+
+    /// Standard assembler syntax, with absolute addresses replaced by their labels.
+    pub fn format(&self, labels: &Labels) -> String {
+        let zero_page = format!("${:02X}", self.operand);
+        let absolute = |addr: u16| {
+            labels
+                .get(&addr)
+                .cloned()
+                .unwrap_or_else(|| format!("${:04X}", addr))
+        };
+
+        let operand = match self.opcode.mode {
+            AddressingMode::Immediate => format!("#${:02X}", self.operand),
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::ZeroPage => zero_page,
+            AddressingMode::ZeroPage_X => format!("{},X", zero_page),
+            AddressingMode::ZeroPage_Y => format!("{},Y", zero_page),
+            AddressingMode::Absolute => absolute(self.operand),
+            AddressingMode::Absolute_X => format!("{},X", absolute(self.operand)),
+            AddressingMode::Absolute_Y => format!("{},Y", absolute(self.operand)),
+            AddressingMode::Indirect => format!("({})", absolute(self.operand)),
+            AddressingMode::Indirect_X => format!("({},X)", zero_page),
+            AddressingMode::Indirect_Y => format!("({}),Y", zero_page),
+            AddressingMode::NoneAddressing => absolute(self.target().unwrap()),
+        };
+
+        if operand.is_empty() {
+            self.opcode.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.opcode.mnemonic, operand)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(&Labels::new()))
+    }
+}
+
+/// A bank of PRG-ROM disassembled by recursive descent: only bytes reached by
+/// following the control flow from the vectors and the extra entry points
+/// are decoded, everything else is listed as data.
+pub struct Disassembly<'a> {
+    data: &'a [u8],
+    origin: u16,
+    pub instructions: BTreeMap<u16, Instruction>,
+    pub labels: Labels,
+}
+
+impl<'a> Disassembly<'a> {
+    /// `data` is mapped at `origin` and must end at or before $FFFF.
+    /// Vectors are only followed when the bank holds them.
+    pub fn new(data: &'a [u8], origin: u16, entries: &[u16]) -> Self {
+        let mut disassembly = Disassembly {
+            data,
+            origin,
+            instructions: BTreeMap::new(),
+            labels: Labels::new(),
+        };
+
+        let mut pending = entries.to_vec();
+        for (vector, name) in VECTORS {
+            if disassembly.contains(vector) && disassembly.contains(vector + 1) {
+                let target =
+                    u16::from_le_bytes([disassembly.read(vector), disassembly.read(vector + 1)]);
+                disassembly
+                    .labels
+                    .entry(target)
+                    .or_insert_with(|| name.to_string());
+                pending.push(target);
+            }
+        }
+
+        while let Some(start) = pending.pop() {
+            disassembly.trace_from(start, &mut pending);
+        }
+        disassembly
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.origin && ((addr - self.origin) as usize) < self.data.len()
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        if self.contains(addr) {
+            self.data[(addr - self.origin) as usize]
+        } else {
+            0
+        }
+    }
+
+    // Decodes a straight run of code, queueing the branch and call targets
+    fn trace_from(&mut self, start: u16, pending: &mut Vec<u16>) {
+        let mut addr = start;
+        while self.contains(addr) && !self.instructions.contains_key(&addr) {
+            let instruction = match Instruction::decode(addr, |addr| self.read(addr)) {
+                Some(instruction) => instruction,
+                None => return,
+            };
+            let last_byte = addr.wrapping_add(instruction.size() - 1);
+            if last_byte < addr || !self.contains(last_byte) {
+                return;
+            }
+
+            if let Some(target) = instruction.target().filter(|&target| self.contains(target)) {
+                self.labels
+                    .entry(target)
+                    .or_insert_with(|| format!("L_{:04X}", target));
+                pending.push(target);
+            }
+
+            let falls_through = instruction.falls_through();
+            self.instructions.insert(addr, instruction);
+            if !falls_through {
+                return;
+            }
+            addr = last_byte.wrapping_add(1);
+        }
+    }
+
+    fn is_vector(&self, addr: u16) -> bool {
+        VECTORS.iter().any(|(vector, _)| *vector == addr)
+    }
+
+    /// One line per instruction, vector or run of data, labels on lines of their own.
+    pub fn listing(&self) -> String {
+        let mut lines = vec![];
+        let end = self.origin as usize + self.data.len();
+        let mut pc = self.origin as usize;
+        while pc < end {
+            let addr = pc as u16;
+            if let Some(label) = self.labels.get(&addr) {
+                lines.push(format!("{}:", label));
+            }
+
+            if let Some(instruction) = self.instructions.get(&addr) {
+                let bytes: Vec<String> = (0..instruction.size())
+                    .map(|offset| format!("{:02X}", self.read(addr + offset)))
+                    .collect();
+                lines.push(format!(
+                    "  {:04X}  {:<8}  {}",
+                    addr,
+                    bytes.join(" "),
+                    instruction.format(&self.labels)
+                ));
+                pc += instruction.size() as usize;
+                continue;
+            }
+
+            if self.is_vector(addr) && pc + 1 < end {
+                let target = u16::from_le_bytes([self.read(addr), self.read(addr + 1)]);
+                let name = self
+                    .labels
+                    .get(&target)
+                    .cloned()
+                    .unwrap_or_else(|| format!("${:04X}", target));
+                lines.push(format!("  {:04X}  .word {}", addr, name));
+                pc += 2;
+                continue;
+            }
+
+            // Data runs up to the next line that needs its own start
+            let mut bytes = vec![];
+            while pc < end && bytes.len() < BYTES_PER_DATA_LINE {
+                let addr = pc as u16;
+                let starts_line = self.labels.contains_key(&addr)
+                    || self.instructions.contains_key(&addr)
+                    || self.is_vector(addr);
+                if !bytes.is_empty() && starts_line {
+                    break;
+                }
+                bytes.push(format!("${:02X}", self.read(addr)));
+                pc += 1;
+            }
+            lines.push(format!("  {:04X}  .byte {}", addr, bytes.join(",")));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Instruction {
+        Instruction::decode(0xC000, |addr| bytes[(addr - 0xC000) as usize]).unwrap()
+    }
+
+    #[test]
+    fn test_format_modes() {
+        assert_eq!(decode(&[0xa9, 0x0f]).to_string(), "LDA #$0F");
+        assert_eq!(decode(&[0xea]).to_string(), "NOP");
+        assert_eq!(decode(&[0x0a]).to_string(), "ASL A");
+        assert_eq!(decode(&[0xa5, 0x10]).to_string(), "LDA $10");
+        assert_eq!(decode(&[0xb5, 0x10]).to_string(), "LDA $10,X");
+        assert_eq!(decode(&[0xb6, 0x10]).to_string(), "LDX $10,Y");
+        assert_eq!(decode(&[0x8d, 0x00, 0x20]).to_string(), "STA $2000");
+        assert_eq!(decode(&[0xbd, 0x34, 0x12]).to_string(), "LDA $1234,X");
+        assert_eq!(decode(&[0xb9, 0x34, 0x12]).to_string(), "LDA $1234,Y");
+        assert_eq!(decode(&[0x6c, 0xfc, 0xff]).to_string(), "JMP ($FFFC)");
+        assert_eq!(decode(&[0xa1, 0x20]).to_string(), "LDA ($20,X)");
+        assert_eq!(decode(&[0xb1, 0x20]).to_string(), "LDA ($20),Y");
+    }
+
+    #[test]
+    fn test_branch_target() {
+        // BNE -2 loops on itself, BEQ +4 skips two words
+        assert_eq!(decode(&[0xd0, 0xfe]).target(), Some(0xC000));
+        assert_eq!(decode(&[0xf0, 0x04]).to_string(), "BEQ $C006");
+        assert_eq!(decode(&[0x20, 0x00, 0x80]).target(), Some(0x8000));
+        assert_eq!(decode(&[0x6c, 0x00, 0x80]).target(), None);
+    }
+
+    #[test]
+    fn test_labels_replace_addresses() {
+        let mut labels = Labels::new();
+        labels.insert(0xC010, "L_C010".to_string());
+        assert_eq!(decode(&[0x4c, 0x10, 0xc0]).format(&labels), "JMP L_C010");
+    }
+
+    // 16 bytes at $FFF0: RESET code, data after its JMP, and the vectors
+    fn vector_bank() -> Vec<u8> {
+        vec![
+            0xa2, 0x00, // FFF0 LDX #$00
+            0xd0, 0x01, // FFF2 BNE $FFF5
+            0x40, // FFF4 RTI, reached only by the NMI vector
+            0x4c, 0xf0, 0xff, // FFF5 JMP $FFF0
+            0xff, 0x02, // FFF8 data that would decode as code
+            0xf4, 0xff, // FFFA NMI
+            0xf0, 0xff, // FFFC RESET
+            0xf4, 0xff, // FFFE IRQ
+        ]
+    }
+
+    #[test]
+    fn test_recursive_descent() {
+        let data = vector_bank();
+        let disassembly = Disassembly::new(&data, 0xFFF0, &[]);
+
+        let code: Vec<u16> = disassembly.instructions.keys().copied().collect();
+        assert_eq!(code, vec![0xFFF0, 0xFFF2, 0xFFF4, 0xFFF5]);
+        assert_eq!(disassembly.labels[&0xFFF0], "RESET");
+        assert_eq!(disassembly.labels[&0xFFF4], "NMI");
+        assert_eq!(disassembly.labels[&0xFFF5], "L_FFF5");
+    }
+
+    #[test]
+    fn test_listing() {
+        let data = vector_bank();
+        let listing = Disassembly::new(&data, 0xFFF0, &[]).listing();
+
+        assert_eq!(
+            listing,
+            [
+                "RESET:",
+                "  FFF0  A2 00     LDX #$00",
+                "  FFF2  D0 01     BNE L_FFF5",
+                "NMI:",
+                "  FFF4  40        RTI",
+                "L_FFF5:",
+                "  FFF5  4C F0 FF  JMP RESET",
+                "  FFF8  .byte $FF,$02",
+                "  FFFA  .word NMI",
+                "  FFFC  .word RESET",
+                "  FFFE  .word NMI",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -4,7 +4,7 @@ use crate::cpu::{
 };
 
 impl CPU {
-    fn add_to_register_a(&mut self, value: u8) {
+    pub(super) fn add_to_register_a(&mut self, value: u8) {
         let result = (self.register_a as u16)
             + (value as u16)
             + ((self.status & StatusFlags::CARRY).bits() as u16);
@@ -60,7 +60,7 @@ mod test {
         cpu.mem_write(0x10, 0b10101010); // Value in memory
         cpu.program_counter = 0x0200;
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_a, 0b10001000);
         assert_eq!(cpu.status.contains(StatusFlags::ZERO), false);
@@ -77,7 +77,7 @@ mod test {
         cpu.mem_write(0x10, 0b10101010);
         cpu.program_counter = 0x0200;
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_a, 0b11101110);
         assert_eq!(cpu.status.contains(StatusFlags::ZERO), false);
@@ -94,7 +94,7 @@ mod test {
         cpu.mem_write(0x10, 0b10101010);
         cpu.program_counter = 0x0200;
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_a, 0b01100110);
         assert_eq!(cpu.status.contains(StatusFlags::ZERO), false);
@@ -111,7 +111,7 @@ mod test {
         cpu.mem_write(0x10, 0b11110000);
         cpu.program_counter = 0x0200;
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_a, 0);
         assert!(cpu.status.contains(StatusFlags::ZERO));
@@ -127,7 +127,7 @@ mod test {
         cpu.mem_write(0x10, 0b10101010);
         cpu.program_counter = 0x0200;
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_a, 0b11111111);
         assert!(cpu.status.contains(StatusFlags::NEGATIVE));
@@ -1,13 +1,17 @@
-use crate::cpu::{cpu::CPU, flags::StatusFlags, memory::Mem};
+use crate::cpu::{core::page_cross, cpu::CPU, flags::StatusFlags, memory::Mem};
 
 impl CPU {
     fn branch(&mut self, condition: bool) {
         if condition {
             let jump: i8 = self.mem_read(self.program_counter) as i8;
-            let jump_addr = self
-                .program_counter
-                .wrapping_add(1)
-                .wrapping_add(jump as u16);
+            let next_instruction = self.program_counter.wrapping_add(1);
+            let jump_addr = next_instruction.wrapping_add(jump as u16);
+
+            // +1 if branch succeeds, +2 if to a new page
+            self.cycles += 1;
+            if page_cross(next_instruction, jump_addr) {
+                self.cycles += 1;
+            }
 
             self.program_counter = jump_addr;
         }
@@ -164,4 +168,30 @@ mod test {
         ]);
         assert_eq!(cpu.mem_read(0x0200), 0x04);
     }
+
+    #[test]
+    fn test_branch_cycles() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0x18, // CLC (2)
+            0xb0, 0x10, // BCS, not taken (2)
+            0x90, 0x00, // BCC +0, taken (2 +1)
+            0x00,
+        ]);
+
+        assert_eq!(cpu.cycles, 7 + 2 + 2 + 3);
+    }
+
+    #[test]
+    fn test_branch_to_new_page_cycles() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0x18, // CLC (2)
+            0x90, 0xfc, // BCC -4 to $7FFF, taken across a page (2 +2)
+        ]);
+
+        assert_eq!(cpu.cycles, 7 + 2 + 4);
+    }
 }
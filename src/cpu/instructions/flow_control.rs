@@ -2,25 +2,19 @@ use crate::cpu::cpu::{AddressingMode, CPU};
 
 impl CPU {
     pub fn jmp(&mut self, mode: &AddressingMode) {
-        let mem_address = self.get_operand_address(mode);
+        let (mem_address, _) = self.get_operand_address(mode);
         self.program_counter = mem_address;
     }
 
     pub fn jsr(&mut self, mode: &AddressingMode) {
-        let mem_address = self.get_operand_address(mode);
-        let pc = self.program_counter + 1;
-        let high = (pc >> 8) as u8;
-        let low = (pc & 0xff) as u8;
-
-        self.push(low);
-        self.push(high);
+        let (mem_address, _) = self.get_operand_address(mode);
+        // Pushes the address of the last byte of the JSR instruction
+        self.push_u16(self.program_counter + 1);
         self.program_counter = mem_address;
     }
 
     pub fn rts(&mut self) {
-        let high = self.pop() as u16;
-        let low = self.pop() as u16;
-        self.program_counter = ((high << 8) | (low)).wrapping_add(1);
+        self.program_counter = self.pop_u16().wrapping_add(1);
     }
 }
 
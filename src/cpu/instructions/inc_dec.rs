@@ -15,7 +15,7 @@ impl CPU {
     }
 
     pub fn inc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let (addr, _) = self.get_operand_address(mode);
         let value = self.mem_read(addr).wrapping_add(1);
 
         self.mem_write(addr, value);
@@ -23,7 +23,7 @@ impl CPU {
     }
 
     pub fn dec(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let (addr, _) = self.get_operand_address(mode);
         let value = self.mem_read(addr).wrapping_sub(1);
 
         self.mem_write(addr, value);
@@ -26,17 +26,17 @@ impl CPU {
     }
 
     pub fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let (addr, _) = self.get_operand_address(mode);
         self.mem_write(addr, self.register_a);
     }
 
     pub fn stx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let (addr, _) = self.get_operand_address(mode);
         self.mem_write(addr, self.register_x);
     }
 
     pub fn sty(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let (addr, _) = self.get_operand_address(mode);
         self.mem_write(addr, self.register_y);
     }
 }
@@ -125,4 +125,34 @@ mod test {
         assert_eq!(cpu.register_y, 5);
         assert_eq!(cpu.mem_read(0x08), 5)
     }
+
+    #[test]
+    fn test_lda_absolute_x_cycles() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0xa2, 0x01, // LDX #$01 (2)
+            0xbd, 0x00, 0x02, // LDA $0200,X (4)
+            0xbd, 0xff, 0x02, // LDA $02FF,X (4 +1 page crossed)
+            0x9d, 0xff, 0x02, // STA $02FF,X (5, no penalty for writes)
+            0x00,
+        ]);
+
+        // 7 cycles for reset
+        assert_eq!(cpu.cycles, 7 + 2 + 4 + 5 + 5);
+    }
+
+    #[test]
+    fn test_lda_indirect_y_cycles() {
+        let mut cpu = CPU::test_new();
+
+        cpu.mem_write_u16(0x10, 0x02ff);
+        cpu.load_and_run(vec![
+            0xa0, 0x01, // LDY #$01 (2)
+            0xb1, 0x10, // LDA ($10),Y (5 +1 page crossed)
+            0x00,
+        ]);
+
+        assert_eq!(cpu.cycles, 7 + 2 + 6);
+    }
 }
@@ -7,3 +7,4 @@ mod load_store;
 mod register_transfers;
 mod shifts;
 mod stack;
+mod unofficial;
@@ -14,7 +14,7 @@ impl CPU {
                 self.register_a = op(self, self.register_a);
             }
             _ => {
-                let addr = self.get_operand_address(mode);
+                let (addr, _) = self.get_operand_address(mode);
                 let value = self.mem_read(addr);
                 let result = op(self, value);
                 self.mem_write(addr, result);
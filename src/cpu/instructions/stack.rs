@@ -15,12 +15,24 @@ impl CPU {
         self.mem_read(addr)
     }
 
+    pub fn push_u16(&mut self, data: u16) {
+        self.push((data >> 8) as u8);
+        self.push((data & 0xff) as u8);
+    }
+
+    pub fn pop_u16(&mut self) -> u16 {
+        let lo = self.pop() as u16;
+        let hi = self.pop() as u16;
+        (hi << 8) | lo
+    }
+
     pub fn pha(&mut self) {
         self.push(self.register_a);
     }
 
     pub fn php(&mut self) {
-        self.push(self.status.bits());
+        // The pushed copy always has BREAK and UNUSED set
+        self.push((self.status | StatusFlags::BREAK | StatusFlags::UNUSED).bits());
     }
 
     pub fn pla(&mut self) {
@@ -29,7 +41,11 @@ impl CPU {
     }
 
     pub fn plp(&mut self) {
-        self.status = StatusFlags::from_bits_truncate(self.pop());
+        // BREAK only exists on the stack, so the pulled bit is ignored
+        let mut status = StatusFlags::from_bits_truncate(self.pop());
+        status.set(StatusFlags::BREAK, self.status.contains(StatusFlags::BREAK));
+        status.insert(StatusFlags::UNUSED);
+        self.status = status;
     }
 
     pub fn txs(&mut self) {
@@ -92,7 +108,8 @@ mod test {
     fn test_plp() {
         let mut cpu = CPU::test_new();
 
-        let init_status = StatusFlags::UNUSED | StatusFlags::BREAK; // Clear all
+        // Status after reset
+        let init_status = StatusFlags::UNUSED | StatusFlags::BREAK | StatusFlags::INTERRUPT;
         cpu.load_and_run(vec![
             0x08, // PHP (push current status)
             0xA9, 0x00, // LDA #$00 to modify flags
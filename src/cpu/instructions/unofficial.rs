@@ -0,0 +1,375 @@
+use crate::cpu::{
+    cpu::{AddressingMode, CPU},
+    flags::StatusFlags,
+    memory::Mem,
+};
+
+impl CPU {
+    pub fn lax(&mut self, mode: &AddressingMode) {
+        let value = self.get_mode_return_value(mode);
+
+        self.register_a = value;
+        self.register_x = value;
+        self.update_zero_and_negative_flags(value);
+    }
+
+    pub fn sax(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_a & self.register_x);
+    }
+
+    pub fn dcp(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(mode);
+        let value = self.mem_read(addr).wrapping_sub(1);
+        self.mem_write(addr, value);
+
+        self.status
+            .set(StatusFlags::CARRY, self.register_a >= value);
+        self.update_zero_and_negative_flags(self.register_a.wrapping_sub(value));
+    }
+
+    pub fn isb(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(mode);
+        let value = self.mem_read(addr).wrapping_add(1);
+        self.mem_write(addr, value);
+
+        self.add_to_register_a(((value as i8).wrapping_neg().wrapping_sub(1)) as u8);
+    }
+
+    pub fn slo(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        let result = value << 1;
+        self.update_carry_asl(value);
+        self.mem_write(addr, result);
+
+        self.register_a |= result;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    pub fn rla(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        let mut result = value << 1;
+        if self.status.contains(StatusFlags::CARRY) {
+            result |= 0b0000_0001;
+        }
+        self.update_carry_asl(value);
+        self.mem_write(addr, result);
+
+        self.register_a &= result;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    pub fn sre(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        let result = value >> 1;
+        self.update_carry_lsr(value);
+        self.mem_write(addr, result);
+
+        self.register_a ^= result;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    pub fn rra(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        let mut result = value >> 1;
+        if self.status.contains(StatusFlags::CARRY) {
+            result |= 0b1000_0000;
+        }
+        self.update_carry_lsr(value);
+        self.mem_write(addr, result);
+
+        self.add_to_register_a(result);
+    }
+
+    pub fn anc(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.status.set(
+            StatusFlags::CARRY,
+            self.status.contains(StatusFlags::NEGATIVE),
+        );
+    }
+
+    pub fn alr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.lsr(&AddressingMode::Accumulator);
+    }
+
+    pub fn arr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.ror(&AddressingMode::Accumulator);
+
+        // Carry and overflow come from bits 6 and 5 of the result
+        let bit_6 = self.register_a & 0b0100_0000 != 0;
+        let bit_5 = self.register_a & 0b0010_0000 != 0;
+        self.status.set(StatusFlags::CARRY, bit_6);
+        self.status.set(StatusFlags::OVERFLOW, bit_6 ^ bit_5);
+    }
+
+    pub fn axs(&mut self, mode: &AddressingMode) {
+        let value = self.get_mode_return_value(mode);
+        let and = self.register_a & self.register_x;
+
+        self.register_x = and.wrapping_sub(value);
+        self.status.set(StatusFlags::CARRY, and >= value);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    pub fn las(&mut self, mode: &AddressingMode) {
+        let value = self.get_mode_return_value(mode) & self.sp;
+
+        self.register_a = value;
+        self.register_x = value;
+        self.sp = value;
+        self.update_zero_and_negative_flags(value);
+    }
+
+    /// Unofficial NOPs still perform the read of their operand.
+    pub fn nop_read(&mut self, mode: &AddressingMode) {
+        match mode {
+            AddressingMode::Implied => {}
+            _ => {
+                self.get_mode_return_value(mode);
+            }
+        }
+    }
+
+    // ---- UNSTABLE ----
+    // These depend on analog effects, the commonly observed behaviour is emulated.
+
+    pub fn xaa(&mut self, mode: &AddressingMode) {
+        let value = self.get_mode_return_value(mode);
+
+        self.register_a = (self.register_a | 0xee) & self.register_x & value;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    pub fn lxa(&mut self, mode: &AddressingMode) {
+        let value = self.get_mode_return_value(mode);
+
+        self.register_a = (self.register_a | 0xee) & value;
+        self.register_x = self.register_a;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    /// Stores `value & (H + 1)`, H being the high byte of the unindexed address.
+    /// When indexing crosses a page the high byte of the address is corrupted too.
+    fn store_and_high(&mut self, mode: &AddressingMode, index: u8, value: u8) {
+        let (addr, page_crossed) = self.get_operand_address(mode);
+        let base = addr.wrapping_sub(index as u16);
+        let result = value & ((base >> 8) as u8).wrapping_add(1);
+
+        let addr = if page_crossed {
+            ((result as u16) << 8) | (addr & 0x00ff)
+        } else {
+            addr
+        };
+        self.mem_write(addr, result);
+    }
+
+    pub fn ahx(&mut self, mode: &AddressingMode) {
+        self.store_and_high(mode, self.register_y, self.register_a & self.register_x);
+    }
+
+    pub fn tas(&mut self, mode: &AddressingMode) {
+        self.sp = self.register_a & self.register_x;
+        self.store_and_high(mode, self.register_y, self.sp);
+    }
+
+    pub fn shy(&mut self, mode: &AddressingMode) {
+        self.store_and_high(mode, self.register_x, self.register_y);
+    }
+
+    pub fn shx(&mut self, mode: &AddressingMode) {
+        self.store_and_high(mode, self.register_y, self.register_x);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::opcodes::CODES_MAP;
+    use crate::error::EmuError;
+
+    #[test]
+    fn test_every_opcode_is_recognized() {
+        for code in 0..=0xffu8 {
+            assert!(CODES_MAP.contains_key(&code), "{:02x} is missing", code);
+        }
+    }
+
+    #[test]
+    fn test_lax() {
+        let mut cpu = CPU::test_new();
+
+        cpu.mem_write(0x10, 0x85);
+        cpu.load_and_run(vec![0xa7, 0x10, 0x00]);
+
+        assert_eq!(cpu.register_a, 0x85);
+        assert_eq!(cpu.register_x, 0x85);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_sax() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0xa9, 0b1100, // LDA
+            0xa2, 0b1010, // LDX
+            0x87, 0x10, // SAX $10
+            0x00,
+        ]);
+
+        assert_eq!(cpu.mem_read(0x10), 0b1000);
+    }
+
+    #[test]
+    fn test_dcp() {
+        let mut cpu = CPU::test_new();
+
+        cpu.mem_write(0x10, 0x43);
+        cpu.load_and_run(vec![0xa9, 0x42, 0xc7, 0x10, 0x00]);
+
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_isb() {
+        let mut cpu = CPU::test_new();
+
+        cpu.mem_write(0x10, 0x01);
+        cpu.load_and_run(vec![
+            0x38, // SEC
+            0xa9, 0x05, // LDA #$05
+            0xe7, 0x10, // ISB $10
+            0x00,
+        ]);
+
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.register_a, 0x03);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_slo() {
+        let mut cpu = CPU::test_new();
+
+        cpu.mem_write(0x10, 0b1000_0001);
+        cpu.load_and_run(vec![0xa9, 0b0000_0100, 0x07, 0x10, 0x00]);
+
+        assert_eq!(cpu.mem_read(0x10), 0b0000_0010);
+        assert_eq!(cpu.register_a, 0b0000_0110);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_rra() {
+        let mut cpu = CPU::test_new();
+
+        cpu.mem_write(0x10, 0b0000_0011);
+        cpu.load_and_run(vec![
+            0x18, // CLC
+            0xa9, 0x10, // LDA #$10
+            0x67, 0x10, // RRA $10, memory becomes 1 and carry is set
+            0x00,
+        ]);
+
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.register_a, 0x12);
+    }
+
+    #[test]
+    fn test_anc_copies_negative_into_carry() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![0xa9, 0xf0, 0x0b, 0x80, 0x00]);
+
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_arr() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0x38, // SEC
+            0xa9, 0xff, // LDA #$FF
+            0x6b, 0x80, // ARR #$80
+            0x00,
+        ]);
+
+        assert_eq!(cpu.register_a, 0xc0);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::OVERFLOW));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_axs() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0xa9, 0x0f, // LDA #$0F
+            0xa2, 0x0c, // LDX #$0C
+            0xcb, 0x02, // AXS #$02
+            0x00,
+        ]);
+
+        assert_eq!(cpu.register_x, 0x0a);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_multi_byte_nops_skip_operands() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0x80, 0xa9, // NOP #imm
+            0x0c, 0xa9, 0xa9, // NOP abs
+            0x1a, // NOP
+            0xe8, // INX
+            0x00,
+        ]);
+
+        assert_eq!(cpu.register_a, 0);
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.cycles, 7 + 2 + 4 + 2 + 2);
+    }
+
+    #[test]
+    fn test_shx_stores_x_and_high_byte() {
+        let mut cpu = CPU::test_new();
+
+        cpu.load_and_run(vec![
+            0xa2, 0xff, // LDX #$FF
+            0xa0, 0x01, // LDY #$01
+            0x9e, 0x00, 0x02, // SHX $0200,Y
+            0x00,
+        ]);
+
+        assert_eq!(cpu.mem_read(0x0201), 0x03);
+    }
+
+    #[test]
+    fn test_trap_unstable_opcodes() {
+        let mut cpu = CPU::test_new();
+        cpu.load(vec![0xa9, 0x01, 0x8b, 0x00, 0x00]);
+        cpu.reset();
+
+        cpu.trap_unstable_opcodes = true;
+        assert_eq!(
+            cpu.run(),
+            Err(EmuError::UnstableOpcode {
+                opcode: 0x8b,
+                pc: 0x8002
+            })
+        );
+    }
+}
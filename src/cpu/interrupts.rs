@@ -0,0 +1,268 @@
+use crate::cpu::{cpu::CPU, flags::StatusFlags, memory::Mem};
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum InterruptType {
+    NMI,
+    IRQ,
+    BRK,
+}
+
+pub struct Interrupt {
+    pub itype: InterruptType,
+    pub vector_addr: u16,
+    // Bits forced on in the status byte pushed to the stack
+    pub b_flag_mask: u8,
+    pub cpu_cycles: u8,
+}
+
+pub const NMI: Interrupt = Interrupt {
+    itype: InterruptType::NMI,
+    vector_addr: 0xFFFA,
+    b_flag_mask: 0b0010_0000,
+    cpu_cycles: 7,
+};
+
+pub const IRQ: Interrupt = Interrupt {
+    itype: InterruptType::IRQ,
+    vector_addr: 0xFFFE,
+    b_flag_mask: 0b0010_0000,
+    cpu_cycles: 7,
+};
+
+pub const BRK: Interrupt = Interrupt {
+    itype: InterruptType::BRK,
+    vector_addr: 0xFFFE,
+    b_flag_mask: 0b0011_0000,
+    // Already counted by the opcode table
+    cpu_cycles: 0,
+};
+
+impl CPU {
+    /// Latches an NMI, it is serviced before the next instruction.
+    pub fn request_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Drives the (level triggered) IRQ input, it is serviced while the interrupt flag is clear.
+    pub fn set_irq_line(&mut self, active: bool) {
+        self.irq_line = active;
+    }
+
+    pub fn interrupt(&mut self, interrupt: Interrupt) {
+        self.push_u16(self.program_counter);
+
+        let mut flag = self.status;
+        flag.remove(StatusFlags::BREAK | StatusFlags::UNUSED);
+        let pushed = flag.bits() | interrupt.b_flag_mask;
+        self.push(pushed);
+
+        self.status.insert(StatusFlags::INTERRUPT);
+
+        self.cycles += interrupt.cpu_cycles as usize;
+        self.bus.tick(interrupt.cpu_cycles as usize);
+        self.program_counter = self.mem_read_u16(interrupt.vector_addr);
+    }
+
+    /// Services a pending NMI or an unmasked IRQ, returns true if one was taken.
+    pub fn poll_interrupts(&mut self) -> bool {
+        if self.bus.poll_nmi_status() {
+            self.nmi_pending = true;
+        }
+
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(NMI);
+            return true;
+        }
+
+        let irq = self.irq_line || self.bus.poll_irq_status();
+        if irq && !self.status.contains(StatusFlags::INTERRUPT) {
+            self.interrupt(IRQ);
+            return true;
+        }
+
+        false
+    }
+
+    pub fn brk(&mut self) {
+        // BRK skips the padding byte that follows it
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.interrupt(BRK);
+    }
+
+    pub fn rti(&mut self) {
+        self.plp();
+        self.program_counter = self.pop_u16();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Rom;
+
+    const HANDLER: u16 = 0x9000;
+
+    fn rom_with_vectors(program: Vec<u8>, handler: Vec<u8>) -> Rom {
+        let mut rom = Rom::from_test_code(program);
+        rom.prg_rom[0x1000..0x1000 + handler.len()].copy_from_slice(&handler);
+        // NMI and IRQ/BRK vectors both point at the handler
+        rom.prg_rom[0x3FFA] = (HANDLER & 0xff) as u8;
+        rom.prg_rom[0x3FFB] = (HANDLER >> 8) as u8;
+        rom.prg_rom[0x3FFE] = (HANDLER & 0xff) as u8;
+        rom.prg_rom[0x3FFF] = (HANDLER >> 8) as u8;
+        rom
+    }
+
+    fn cpu_with_vectors(program: Vec<u8>, handler: Vec<u8>) -> CPU {
+        let mut cpu = CPU::test_new();
+        cpu.bus.load_rom(rom_with_vectors(program, handler));
+        cpu.reset();
+        cpu
+    }
+
+    fn stack_byte(cpu: &mut CPU, offset: u8) -> u8 {
+        cpu.mem_read(0x0100 | cpu.sp.wrapping_add(offset) as u16)
+    }
+
+    #[test]
+    fn test_nmi_pushes_pc_and_status() {
+        let mut cpu = cpu_with_vectors(vec![0xa9, 0x01, 0x00], vec![0x00]);
+
+        cpu.request_nmi();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, HANDLER + 1);
+        assert_eq!(cpu.register_a, 0x00);
+        assert_eq!(cpu.sp, 0xfc);
+        assert!(cpu.status.contains(StatusFlags::INTERRUPT));
+        // BREAK clear, UNUSED set
+        assert_eq!(stack_byte(&mut cpu, 1) & 0b0011_0000, 0b0010_0000);
+        assert_eq!(stack_byte(&mut cpu, 2), 0x00);
+        assert_eq!(stack_byte(&mut cpu, 3), 0x80);
+    }
+
+    #[test]
+    fn test_brk_is_a_software_interrupt() {
+        let mut cpu = cpu_with_vectors(vec![0x00, 0xff], vec![0x00]);
+
+        cpu.halt_on_brk = false;
+        cpu.run_with_callback(|cpu| {
+            if cpu.program_counter == HANDLER {
+                cpu.halt_on_brk = true;
+            }
+        })
+        .unwrap();
+
+        assert_eq!(cpu.program_counter, HANDLER + 1);
+        // BREAK and UNUSED set
+        assert_eq!(stack_byte(&mut cpu, 1) & 0b0011_0000, 0b0011_0000);
+        // Return address skips the padding byte
+        assert_eq!(stack_byte(&mut cpu, 2), 0x02);
+        assert_eq!(stack_byte(&mut cpu, 3), 0x80);
+    }
+
+    #[test]
+    fn test_rti_returns_from_nmi() {
+        let mut cpu = cpu_with_vectors(
+            vec![
+                0xa9, 0x42, // LDA #$42
+                0x00,
+            ],
+            vec![
+                0x38, // SEC
+                0x40, // RTI
+            ],
+        );
+
+        cpu.status.remove(StatusFlags::INTERRUPT);
+        cpu.request_nmi();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.sp, 0xff);
+        // Status is restored
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::INTERRUPT));
+    }
+
+    #[test]
+    fn test_irq_is_masked_by_interrupt_flag() {
+        let mut cpu = cpu_with_vectors(
+            vec![
+                0xa9, 0x42, // LDA #$42
+                0x00,
+            ],
+            vec![0x00],
+        );
+
+        cpu.set_irq_line(true);
+        cpu.status.insert(StatusFlags::INTERRUPT);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.sp, 0xff);
+    }
+
+    #[test]
+    fn test_reset_masks_irq() {
+        let mut cpu = cpu_with_vectors(vec![0xa9, 0x42, 0x00], vec![0x00]);
+
+        cpu.set_irq_line(true);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.sp, 0xff);
+    }
+
+    #[test]
+    fn test_irq_vectors_when_unmasked() {
+        let mut cpu = cpu_with_vectors(vec![0xa9, 0x42, 0x00], vec![0x00]);
+
+        cpu.status.remove(StatusFlags::INTERRUPT);
+        cpu.set_irq_line(true);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, HANDLER + 1);
+        assert_eq!(cpu.register_a, 0x00);
+        assert_eq!(stack_byte(&mut cpu, 1) & 0b0001_0000, 0);
+    }
+
+    #[test]
+    fn test_cartridge_irq_reaches_cpu() {
+        let mut rom = rom_with_vectors(vec![0xa9, 0x42, 0x00], vec![0x00]);
+        rom.mapper = 4;
+        let mut cpu = CPU::test_new();
+        cpu.bus.load_rom(rom);
+        cpu.reset();
+        {
+            let mut cartridge = cpu.bus.mapper.borrow_mut();
+            cartridge.write_prg(0xC000, 0);
+            cartridge.write_prg(0xE001, 0);
+            cartridge.ppu_bus_address(0x1000);
+        }
+        cpu.status.remove(StatusFlags::INTERRUPT);
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x00);
+        assert_eq!(cpu.sp, 0xfc);
+    }
+
+    #[test]
+    fn test_ppu_vblank_raises_nmi() {
+        let mut cpu = cpu_with_vectors(
+            vec![
+                0xa9, 0x80, // LDA #$80
+                0x8d, 0x00, 0x20, // STA $2000, enable NMI on vblank
+                0x4c, 0x05, 0x80, // JMP $8005
+            ],
+            vec![0x00],
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, HANDLER + 1);
+        assert_eq!(cpu.bus.ppu.scanline, 241);
+    }
+}
@@ -1,6 +1,9 @@
 pub mod core;
 pub mod cpu;
+pub mod disasm;
 pub mod flags;
 pub mod instructions;
+pub mod interrupts;
 pub mod memory;
 pub mod opcodes;
+pub mod trace;
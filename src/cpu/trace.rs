@@ -0,0 +1,398 @@
+use std::fmt;
+use std::io::Write;
+
+use crate::{
+    cpu::{cpu::AddressingMode, cpu::CPU, opcodes},
+    error::EmuError,
+};
+
+impl CPU {
+    /// Runs like `run`, writing a nestest style trace line before every instruction.
+    /// Stops with `EmuError::Io` when a line can't be written.
+    pub fn run_with_trace<W: Write>(&mut self, out: &mut W) -> Result<(), EmuError> {
+        loop {
+            writeln!(out, "{}", trace(self))?;
+            if !self.step()? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Trace reads must not disturb the machine, so they go through `peek`
+fn peek_u16(cpu: &CPU, pos: u16) -> u16 {
+    let lo = cpu.bus.peek(pos) as u16;
+    let hi = cpu.bus.peek(pos.wrapping_add(1)) as u16;
+    (hi << 8) | lo
+}
+
+fn peek_zero_page_u16(cpu: &CPU, pos: u8) -> u16 {
+    let lo = cpu.bus.peek(pos as u16) as u16;
+    let hi = cpu.bus.peek(pos.wrapping_add(1) as u16) as u16;
+    (hi << 8) | lo
+}
+
+/// Formats the instruction at the program counter together with the CPU state,
+/// in the format used by nestest.log:
+///
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+pub fn trace(cpu: &CPU) -> String {
+    let code = cpu.bus.peek(cpu.program_counter);
+    let ops = opcodes::CODES_MAP[&code];
+
+    let begin = cpu.program_counter;
+    let mut hex_dump = vec![code];
+
+    let (mem_addr, stored_value) = match ops.mode {
+        AddressingMode::Immediate
+        | AddressingMode::Implied
+        | AddressingMode::Accumulator
+        | AddressingMode::NoneAddressing
+        | AddressingMode::Indirect => (0, 0),
+        _ => {
+            let addr = effective_address(cpu, &ops.mode, begin.wrapping_add(1));
+            (addr, cpu.bus.peek(addr))
+        }
+    };
+
+    let tmp = match ops.bytes {
+        1 => match ops.mode {
+            AddressingMode::Accumulator => "A ".to_string(),
+            _ => String::from(""),
+        },
+        2 => {
+            let address = cpu.bus.peek(begin.wrapping_add(1));
+            hex_dump.push(address);
+
+            match ops.mode {
+                AddressingMode::Immediate => format!("#${:02x}", address),
+                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::ZeroPage_X => format!(
+                    "${:02x},X @ {:02x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::ZeroPage_Y => format!(
+                    "${:02x},Y @ {:02x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::Indirect_X => format!(
+                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
+                    address,
+                    address.wrapping_add(cpu.register_x),
+                    mem_addr,
+                    stored_value
+                ),
+                AddressingMode::Indirect_Y => format!(
+                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
+                    address,
+                    mem_addr.wrapping_sub(cpu.register_y as u16),
+                    mem_addr,
+                    stored_value
+                ),
+                AddressingMode::NoneAddressing => {
+                    // Relative branch, resolve the target
+                    let address = begin.wrapping_add(2).wrapping_add((address as i8) as u16);
+                    format!("${:04x}", address)
+                }
+                _ => panic!(
+                    "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
+                    ops.mode, ops.code
+                ),
+            }
+        }
+        3 => {
+            let address_lo = cpu.bus.peek(begin.wrapping_add(1));
+            let address_hi = cpu.bus.peek(begin.wrapping_add(2));
+            hex_dump.push(address_lo);
+            hex_dump.push(address_hi);
+
+            let address = peek_u16(cpu, begin.wrapping_add(1));
+
+            match ops.mode {
+                AddressingMode::Indirect => {
+                    // JMP ($xxFF) wraps within the page
+                    let jmp_addr = if address & 0x00FF == 0x00FF {
+                        let lo = cpu.bus.peek(address);
+                        let hi = cpu.bus.peek(address & 0xFF00);
+                        (hi as u16) << 8 | (lo as u16)
+                    } else {
+                        peek_u16(cpu, address)
+                    };
+
+                    format!("(${:04x}) = {:04x}", address, jmp_addr)
+                }
+                // JMP and JSR show no memory value
+                AddressingMode::Absolute if ops.code == 0x4c || ops.code == 0x20 => {
+                    format!("${:04x}", address)
+                }
+                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::Absolute_X => format!(
+                    "${:04x},X @ {:04x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::Absolute_Y => format!(
+                    "${:04x},Y @ {:04x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                _ => panic!(
+                    "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
+                    ops.mode, ops.code
+                ),
+            }
+        }
+        _ => String::from(""),
+    };
+
+    let hex_str = hex_dump
+        .iter()
+        .map(|z| format!("{:02x}", z))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let asm_str = format!("{:04x}  {:8} {: >4} {}", begin, hex_str, ops.mnemonic, tmp)
+        .trim()
+        .to_string();
+
+    format!(
+        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{:>3},{:>3} CYC:{}",
+        asm_str,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits(),
+        cpu.sp,
+        cpu.bus.ppu.scanline,
+        cpu.bus.ppu.cycles,
+        cpu.cycles,
+    )
+    .to_ascii_uppercase()
+}
+
+// Same as `CPU::get_operand_address`, without side effects
+fn effective_address(cpu: &CPU, mode: &AddressingMode, addr: u16) -> u16 {
+    match mode {
+        AddressingMode::ZeroPage => cpu.bus.peek(addr) as u16,
+        AddressingMode::Absolute => peek_u16(cpu, addr),
+        AddressingMode::ZeroPage_X => cpu.bus.peek(addr).wrapping_add(cpu.register_x) as u16,
+        AddressingMode::ZeroPage_Y => cpu.bus.peek(addr).wrapping_add(cpu.register_y) as u16,
+        AddressingMode::Absolute_X => peek_u16(cpu, addr).wrapping_add(cpu.register_x as u16),
+        AddressingMode::Absolute_Y => peek_u16(cpu, addr).wrapping_add(cpu.register_y as u16),
+        AddressingMode::Indirect_X => {
+            let ptr = cpu.bus.peek(addr).wrapping_add(cpu.register_x);
+            peek_zero_page_u16(cpu, ptr)
+        }
+        AddressingMode::Indirect_Y => {
+            let base = cpu.bus.peek(addr);
+            peek_zero_page_u16(cpu, base).wrapping_add(cpu.register_y as u16)
+        }
+        _ => panic!("mode {:?} has no effective address", mode),
+    }
+}
+
+/// The first line where a trace differs from the reference log.
+#[derive(Debug, PartialEq)]
+pub struct TraceDivergence {
+    // 1-based, like an editor
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for TraceDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "trace diverges at line {}\nexpected: {}\n  actual: {}",
+            self.line, self.expected, self.actual
+        )
+    }
+}
+
+/// Compares trace lines against a reference log, trailing whitespace is ignored.
+pub fn first_divergence<S: AsRef<str>>(actual: &[S], reference: &str) -> Option<TraceDivergence> {
+    let mut expected_lines = reference.lines();
+    for (i, actual) in actual.iter().enumerate() {
+        let actual = actual.as_ref().trim_end();
+        let expected = expected_lines.next().unwrap_or("").trim_end();
+        if actual != expected {
+            return Some(TraceDivergence {
+                line: i + 1,
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            });
+        }
+    }
+    None
+}
+
+/// Steps the CPU once per reference line, stopping at the first line that differs.
+pub fn compare_with_log(cpu: &mut CPU, reference: &str) -> Result<(), TraceDivergence> {
+    for (i, expected) in reference.lines().enumerate() {
+        let actual = trace(cpu);
+        if let Some(mut divergence) = first_divergence(&[actual], expected) {
+            divergence.line = i + 1;
+            return Err(divergence);
+        }
+        match cpu.step() {
+            Ok(true) => {}
+            Ok(false) => break,
+            // Report the fault in place of the line the CPU never reached
+            Err(fault) => {
+                return Err(TraceDivergence {
+                    line: i + 2,
+                    expected: reference.lines().nth(i + 1).unwrap_or("").to_string(),
+                    actual: fault.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cpu::memory::Mem, rom::Rom};
+
+    #[test]
+    fn test_format_trace() {
+        let mut cpu = CPU::test_new();
+        cpu.mem_write(100, 0xa2);
+        cpu.mem_write(101, 0x01);
+        cpu.mem_write(102, 0xca);
+        cpu.mem_write(103, 0x88);
+        cpu.mem_write(104, 0x00);
+        cpu.program_counter = 0x64;
+        cpu.register_a = 1;
+        cpu.register_x = 2;
+        cpu.register_y = 3;
+
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu));
+        })
+        .unwrap();
+
+        assert_eq!(
+            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:30 SP:FF PPU:  0,  0 CYC:0",
+            result[0]
+        );
+        assert_eq!(
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:30 SP:FF PPU:  0,  6 CYC:2",
+            result[1]
+        );
+        assert_eq!(
+            "0067  88        DEY                             A:01 X:00 Y:03 P:32 SP:FF PPU:  0, 12 CYC:4",
+            result[2]
+        );
+    }
+
+    #[test]
+    fn test_format_mem_access() {
+        let mut cpu = CPU::test_new();
+        // ORA ($33), Y
+        cpu.mem_write(100, 0x11);
+        cpu.mem_write(101, 0x33);
+        cpu.mem_write(102, 0x00);
+
+        // data
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
+
+        // target cell
+        cpu.mem_write(0x400, 0xAA);
+
+        cpu.program_counter = 0x64;
+        cpu.register_y = 0;
+
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu));
+        })
+        .unwrap();
+
+        assert_eq!(
+            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:30 SP:FF PPU:  0,  0 CYC:0",
+            result[0]
+        );
+    }
+
+    #[test]
+    fn test_format_branch_and_jump_targets() {
+        let mut cpu = CPU::test_new();
+        cpu.load(vec![
+            0xd0, 0x02, // BNE +2
+            0x4c, 0x34, 0x12, // JMP $1234
+        ]);
+        cpu.program_counter = 0x8000;
+
+        assert!(trace(&cpu).starts_with("8000  D0 02     BNE $8004 "));
+        cpu.program_counter = 0x8002;
+        assert!(trace(&cpu).starts_with("8002  4C 34 12  JMP $1234 "));
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let reference = "a\nb\nc\n";
+
+        assert_eq!(first_divergence(&["a", "b "], reference), None);
+        assert_eq!(
+            first_divergence(&["a", "x", "c"], reference),
+            Some(TraceDivergence {
+                line: 2,
+                expected: "b".to_string(),
+                actual: "x".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_compare_with_log_reports_first_divergent_line() {
+        let mut cpu = CPU::test_new();
+        cpu.load(vec![0xe8, 0xe8, 0x00]);
+        cpu.program_counter = 0x8000;
+
+        let reference = "\
+8000  E8        INX                             A:00 X:00 Y:00 P:30 SP:FF PPU:  0,  0 CYC:0
+8001  E8        INX                             A:00 X:05 Y:00 P:30 SP:FF PPU:  0,  6 CYC:2
+";
+
+        let divergence = compare_with_log(&mut cpu, reference).unwrap_err();
+
+        assert_eq!(divergence.line, 2);
+        assert!(divergence.actual.contains("X:01"));
+    }
+
+    #[test]
+    fn test_run_with_trace_reports_write_errors() {
+        let mut cpu = CPU::test_new();
+        cpu.load(vec![0xe8, 0x00]);
+        cpu.program_counter = 0x8000;
+
+        // A full buffer, like a closed pipe, refuses the first line
+        let mut out: &mut [u8] = &mut [];
+        let result = cpu.run_with_trace(&mut out);
+
+        assert!(matches!(result, Err(EmuError::Io(_))));
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    /// Runs nestest.nes in automation mode against its reference log.
+    /// Both files are expected next to Cargo.toml.
+    #[test]
+    #[ignore = "needs nestest.nes and nestest.log"]
+    fn test_nestest() {
+        let raw = std::fs::read("nestest.nes").unwrap();
+        let reference = std::fs::read_to_string("nestest.log").unwrap();
+
+        let mut cpu = CPU::new(crate::bus::Bus::new(Rom::new(&raw).unwrap()).unwrap());
+        cpu.reset();
+        // Automation mode starts at $C000 with the power-up state
+        cpu.program_counter = 0xC000;
+        cpu.status = crate::cpu::flags::StatusFlags::from_bits_truncate(0x24);
+        cpu.sp = 0xfd;
+
+        if let Err(divergence) = compare_with_log(&mut cpu, &reference) {
+            panic!("{}", divergence);
+        }
+    }
+}
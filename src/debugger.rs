@@ -0,0 +1,603 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    ops::RangeInclusive,
+};
+
+use crate::{
+    bus::{Access, Bus},
+    cpu::{cpu::CPU, flags::StatusFlags, memory::Mem, opcodes, trace::trace},
+    error::EmuError,
+};
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const DEFAULT_DUMP_LENGTH: usize = 64;
+
+const HELP: &str = "commands:
+  break <addr>                stop before the instruction at addr (b)
+  break read <addr>[-<end>]   stop after any read in the range, opcode fetches included
+  break write <addr>[-<end>]  stop after any write in the range
+  break op <opcode>           stop before any instruction with this opcode
+  delete <n>                  remove breakpoint n
+  list                        list breakpoints
+  step [count]                execute instructions (s)
+  next                        step over a JSR (n)
+  finish                      run until the current subroutine returns
+  continue                    run until a breakpoint or halt (c)
+  regs                        print registers and flags (r)
+  dump <addr> [length]        hexdump memory (x)
+  quit                        leave the debugger (q)
+addresses and opcodes are hex with an optional $ or 0x prefix, counts are decimal
+an empty line repeats the last command";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Breakpoint {
+    /// Before the instruction at this address runs
+    Pc(u16),
+    /// After an instruction that read from the range
+    Read(RangeInclusive<u16>),
+    /// After an instruction that wrote to the range
+    Write(RangeInclusive<u16>),
+    /// Before any instruction with this opcode runs
+    Opcode(u8),
+}
+
+fn format_range(range: &RangeInclusive<u16>) -> String {
+    if range.start() == range.end() {
+        format!("${:04X}", range.start())
+    } else {
+        format!("${:04X}-${:04X}", range.start(), range.end())
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(addr) => write!(f, "pc ${:04X}", addr),
+            Breakpoint::Read(range) => write!(f, "read {}", format_range(range)),
+            Breakpoint::Write(range) => write!(f, "write {}", format_range(range)),
+            Breakpoint::Opcode(opcode) => write!(f, "opcode ${:02X}", opcode),
+        }
+    }
+}
+
+/// Why execution stopped
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    /// Index of the breakpoint that was hit
+    Breakpoint(usize),
+    /// The step, step over or finish completed
+    Done,
+    /// The CPU jammed, or ran BRK with `halt_on_brk` set
+    Halted,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Break(Breakpoint),
+    Delete(usize),
+    List,
+    Step(usize),
+    Next,
+    Finish,
+    Continue,
+    Regs,
+    Dump(u16, usize),
+    Help,
+    Quit,
+}
+
+/// Hex number with an optional $ or 0x prefix
+pub fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Not a hex number: {}", text))
+}
+
+fn parse_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+        None => (parse_hex(text)?, parse_hex(text)?),
+    };
+    if end < start {
+        return Err(format!("Empty range: {}", text));
+    }
+    Ok(start..=end)
+}
+
+fn parse_count(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("Not a decimal count: {}", text))
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command = match words.as_slice() {
+        ["break" | "b", "read", range] => Command::Break(Breakpoint::Read(parse_range(range)?)),
+        ["break" | "b", "write", range] => Command::Break(Breakpoint::Write(parse_range(range)?)),
+        ["break" | "b", "op", opcode] => {
+            let opcode = u8::try_from(parse_hex(opcode)?)
+                .map_err(|_| format!("Not an opcode: {}", opcode))?;
+            Command::Break(Breakpoint::Opcode(opcode))
+        }
+        ["break" | "b", addr] => Command::Break(Breakpoint::Pc(parse_hex(addr)?)),
+        ["delete", index] => Command::Delete(parse_count(index)?),
+        ["list"] => Command::List,
+        ["step" | "s"] => Command::Step(1),
+        ["step" | "s", count] => match parse_count(count)? {
+            0 => return Err("Step count must be at least 1".to_string()),
+            count => Command::Step(count),
+        },
+        ["next" | "n"] => Command::Next,
+        ["finish"] => Command::Finish,
+        ["continue" | "c"] => Command::Continue,
+        ["regs" | "r"] => Command::Regs,
+        ["dump" | "x", addr] => Command::Dump(parse_hex(addr)?, DEFAULT_DUMP_LENGTH),
+        ["dump" | "x", addr, length] => Command::Dump(parse_hex(addr)?, parse_count(length)?),
+        ["help" | "h"] => Command::Help,
+        ["quit" | "q"] => Command::Quit,
+        _ => return Err(format!("Unknown command: {} (try help)", line.trim())),
+    };
+    Ok(command)
+}
+
+// N V U B D I Z C, upper case when set
+fn flag_letters(status: StatusFlags) -> String {
+    [
+        (StatusFlags::NEGATIVE, 'N'),
+        (StatusFlags::OVERFLOW, 'V'),
+        (StatusFlags::UNUSED, 'U'),
+        (StatusFlags::BREAK, 'B'),
+        (StatusFlags::DECIMAL, 'D'),
+        (StatusFlags::INTERRUPT, 'I'),
+        (StatusFlags::ZERO, 'Z'),
+        (StatusFlags::CARRY, 'C'),
+    ]
+    .iter()
+    .map(|&(flag, letter)| {
+        if status.contains(flag) {
+            letter
+        } else {
+            letter.to_ascii_lowercase()
+        }
+    })
+    .collect()
+}
+
+pub fn registers(cpu: &CPU) -> String {
+    format!(
+        "A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} PC:{:04X} CYC:{}\nP:{:02X} {}",
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.sp,
+        cpu.program_counter,
+        cpu.cycles,
+        cpu.status.bits(),
+        flag_letters(cpu.status)
+    )
+}
+
+/// 16 bytes per line, each line starting with its address.
+pub fn hexdump<M: Mem>(mem: &mut M, start: u16, length: usize) -> String {
+    let bytes: Vec<u8> = (0..length)
+        .map(|offset| mem.mem_read(start.wrapping_add(offset as u16)))
+        .collect();
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!(
+                "{:04X}  {}",
+                start.wrapping_add(row as u16 * 16),
+                hex.join(" ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Side effect free view of the bus, so a dump of $2002 doesn't acknowledge vblank
+struct Peek<'a>(&'a Bus);
+
+impl Mem for Peek<'_> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.0.peek(addr)
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {
+        // Dumps only read
+    }
+}
+
+// The trace line, which needs a known opcode
+fn current_instruction(cpu: &CPU) -> String {
+    let code = cpu.bus.peek(cpu.program_counter);
+    if opcodes::CODES_MAP.contains_key(&code) {
+        trace(cpu)
+    } else {
+        format!("{:04X}  {:02X}        ???", cpu.program_counter, code)
+    }
+}
+
+/// Breakpoints and stepping on top of `CPU::step`, driven by text commands.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        (index < self.breakpoints.len()).then(|| self.breakpoints.remove(index))
+    }
+
+    fn hit_before(&self, pc: u16, opcode: u8) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| match breakpoint {
+                Breakpoint::Pc(addr) => *addr == pc,
+                Breakpoint::Opcode(code) => *code == opcode,
+                _ => false,
+            })
+    }
+
+    fn hit_after(&self, accesses: &[Access]) -> Option<usize> {
+        let touched = |range: &RangeInclusive<u16>, write: bool| {
+            accesses
+                .iter()
+                .any(|access| access.write == write && range.contains(&access.addr))
+        };
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| match breakpoint {
+                Breakpoint::Read(range) => touched(range, false),
+                Breakpoint::Write(range) => touched(range, true),
+                _ => false,
+            })
+    }
+
+    /// Steps until `done` returns true, a breakpoint hits or the CPU halts.
+    /// `done` gets the opcode that was at the PC and the stack pointer from
+    /// before the step. Breakpoints on the first instruction are skipped, so
+    /// running again leaves the one that stopped us.
+    fn run_until<F>(&self, cpu: &mut CPU, mut done: F) -> Result<Stop, EmuError>
+    where
+        F: FnMut(&CPU, u8, u8) -> bool,
+    {
+        let watching = self
+            .breakpoints
+            .iter()
+            .any(|breakpoint| matches!(breakpoint, Breakpoint::Read(_) | Breakpoint::Write(_)));
+        let mut first = true;
+        loop {
+            let opcode = cpu.bus.peek(cpu.program_counter);
+            let hit = self.hit_before(cpu.program_counter, opcode);
+            if let Some(index) = hit.filter(|_| !first) {
+                return Ok(Stop::Breakpoint(index));
+            }
+            first = false;
+
+            let sp = cpu.sp;
+            if watching {
+                cpu.bus.access_log = Some(vec![]);
+            }
+            let result = cpu.step();
+            let accesses = cpu.bus.access_log.take().unwrap_or_default();
+            if !result? {
+                return Ok(Stop::Halted);
+            }
+            if let Some(index) = self.hit_after(&accesses) {
+                return Ok(Stop::Breakpoint(index));
+            }
+            if done(cpu, opcode, sp) {
+                return Ok(Stop::Done);
+            }
+        }
+    }
+
+    /// Executes `count` instructions, a serviced interrupt counts as one.
+    pub fn step(&self, cpu: &mut CPU, count: usize) -> Result<Stop, EmuError> {
+        if count == 0 {
+            return Ok(Stop::Done);
+        }
+        let mut remaining = count;
+        self.run_until(cpu, |_, _, _| {
+            remaining = remaining.saturating_sub(1);
+            remaining == 0
+        })
+    }
+
+    /// Like `step`, but a JSR runs until its subroutine has returned.
+    pub fn step_over(&self, cpu: &mut CPU) -> Result<Stop, EmuError> {
+        if cpu.bus.peek(cpu.program_counter) != JSR {
+            return self.step(cpu, 1);
+        }
+        let return_addr = cpu.program_counter.wrapping_add(3);
+        let sp = cpu.sp;
+        // The stack pointer tells a return from a recursive call to the same place
+        self.run_until(cpu, |cpu, _, _| {
+            cpu.program_counter == return_addr && cpu.sp == sp
+        })
+    }
+
+    /// Runs until an RTS returns from the subroutine we are in.
+    pub fn finish(&self, cpu: &mut CPU) -> Result<Stop, EmuError> {
+        let frame_sp = cpu.sp;
+        self.run_until(cpu, |cpu, opcode, sp| {
+            // An interrupt taken instead of the RTS would have pushed instead
+            let returned = opcode == RTS && cpu.sp == sp.wrapping_add(2);
+            returned && cpu.sp > frame_sp
+        })
+    }
+
+    pub fn resume(&self, cpu: &mut CPU) -> Result<Stop, EmuError> {
+        self.run_until(cpu, |_, _, _| false)
+    }
+
+    fn report(&self, cpu: &CPU, stop: Result<Stop, EmuError>) -> String {
+        match stop {
+            Ok(Stop::Done) => current_instruction(cpu),
+            Ok(Stop::Breakpoint(index)) => format!(
+                "Breakpoint {}: {}\n{}",
+                index,
+                self.breakpoints[index],
+                current_instruction(cpu)
+            ),
+            Ok(Stop::Halted) => format!("CPU halted\n{}", current_instruction(cpu)),
+            Err(err) => format!("Emulation stopped: {}", err),
+        }
+    }
+
+    /// Carries out a command and returns what to print.
+    pub fn execute(&mut self, cpu: &mut CPU, command: Command) -> String {
+        match command {
+            Command::Break(breakpoint) => {
+                let text = format!("Breakpoint {}: {}", self.breakpoints.len(), breakpoint);
+                self.add_breakpoint(breakpoint);
+                text
+            }
+            Command::Delete(index) => match self.remove_breakpoint(index) {
+                Some(breakpoint) => format!("Deleted breakpoint {}: {}", index, breakpoint),
+                None => format!("No breakpoint {}", index),
+            },
+            Command::List if self.breakpoints.is_empty() => "No breakpoints".to_string(),
+            Command::List => self
+                .breakpoints
+                .iter()
+                .enumerate()
+                .map(|(index, breakpoint)| format!("{}: {}", index, breakpoint))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Command::Step(count) => {
+                let stop = self.step(cpu, count);
+                self.report(cpu, stop)
+            }
+            Command::Next => {
+                let stop = self.step_over(cpu);
+                self.report(cpu, stop)
+            }
+            Command::Finish => {
+                let stop = self.finish(cpu);
+                self.report(cpu, stop)
+            }
+            Command::Continue => {
+                let stop = self.resume(cpu);
+                self.report(cpu, stop)
+            }
+            Command::Regs => registers(cpu),
+            Command::Dump(addr, length) => hexdump(&mut Peek(&cpu.bus), addr, length),
+            Command::Help => HELP.to_string(),
+            Command::Quit => String::new(),
+        }
+    }
+
+    /// Reads commands from `input` until it ends or says quit.
+    pub fn repl<R: BufRead, W: Write>(
+        &mut self,
+        cpu: &mut CPU,
+        input: R,
+        output: &mut W,
+    ) -> io::Result<()> {
+        writeln!(output, "{}", current_instruction(cpu))?;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            let line = if line.trim().is_empty() {
+                self.last_command.clone()
+            } else {
+                line
+            };
+            if line.is_empty() {
+                continue;
+            }
+            self.last_command = line.clone();
+
+            match parse_command(&line) {
+                Ok(Command::Quit) => return Ok(()),
+                Ok(command) => {
+                    let text = self.execute(cpu, command);
+                    writeln!(output, "{}", text)?;
+                }
+                Err(message) => writeln!(output, "{}", message)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // JSR $8008; LDX #$02; BRK; (pad); $8008: LDA #$01; STA $10; RTS
+    fn subroutine_cpu() -> CPU {
+        let mut cpu = CPU::test_new();
+        cpu.load(vec![
+            0x20, 0x08, 0x80, 0xa2, 0x02, 0x00, 0xea, 0xea, 0xa9, 0x01, 0x85, 0x10, 0x60,
+        ]);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_step() {
+        let mut cpu = subroutine_cpu();
+        let debugger = Debugger::new();
+
+        assert_eq!(debugger.step(&mut cpu, 0), Ok(Stop::Done));
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(debugger.step(&mut cpu, 1), Ok(Stop::Done));
+        assert_eq!(cpu.program_counter, 0x8008);
+        assert_eq!(debugger.step(&mut cpu, 2), Ok(Stop::Done));
+        assert_eq!(cpu.program_counter, 0x800c);
+    }
+
+    #[test]
+    fn test_step_over_jsr() {
+        let mut cpu = subroutine_cpu();
+        let debugger = Debugger::new();
+
+        assert_eq!(debugger.step_over(&mut cpu), Ok(Stop::Done));
+        assert_eq!(cpu.program_counter, 0x8003);
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.sp, 0xff);
+    }
+
+    #[test]
+    fn test_finish() {
+        let mut cpu = subroutine_cpu();
+        let debugger = Debugger::new();
+        debugger.step(&mut cpu, 1).unwrap();
+
+        assert_eq!(debugger.finish(&mut cpu), Ok(Stop::Done));
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+
+    #[test]
+    fn test_pc_breakpoint() {
+        let mut cpu = subroutine_cpu();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Pc(0x800a));
+
+        assert_eq!(debugger.resume(&mut cpu), Ok(Stop::Breakpoint(0)));
+        assert_eq!(cpu.program_counter, 0x800a);
+        // Continuing leaves the breakpoint behind
+        assert_eq!(debugger.resume(&mut cpu), Ok(Stop::Halted));
+    }
+
+    #[test]
+    fn test_write_breakpoint() {
+        let mut cpu = subroutine_cpu();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Read(0x0000..=0x00ff));
+        debugger.add_breakpoint(Breakpoint::Write(0x0010..=0x0010));
+
+        // The JSR pushes to $01FF/$01FE, outside both ranges
+        assert_eq!(debugger.resume(&mut cpu), Ok(Stop::Breakpoint(1)));
+        assert_eq!(cpu.program_counter, 0x800c);
+        assert_eq!(cpu.bus.access_log, None);
+    }
+
+    #[test]
+    fn test_opcode_breakpoint() {
+        let mut cpu = subroutine_cpu();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Opcode(RTS));
+
+        assert_eq!(debugger.resume(&mut cpu), Ok(Stop::Breakpoint(0)));
+        assert_eq!(cpu.program_counter, 0x800c);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("break $c000"),
+            Ok(Command::Break(Breakpoint::Pc(0xc000)))
+        );
+        assert_eq!(
+            parse_command("b write 0x2000-2007"),
+            Ok(Command::Break(Breakpoint::Write(0x2000..=0x2007)))
+        );
+        assert_eq!(
+            parse_command("break op 20"),
+            Ok(Command::Break(Breakpoint::Opcode(0x20)))
+        );
+        assert_eq!(parse_command("s 10"), Ok(Command::Step(10)));
+        assert!(parse_command("step 0").is_err());
+        assert_eq!(
+            parse_command("x 0"),
+            Ok(Command::Dump(0, DEFAULT_DUMP_LENGTH))
+        );
+        assert!(parse_command("break read 10-0").is_err());
+        assert!(parse_command("break op 100").is_err());
+        assert!(parse_command("jump").is_err());
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let mut cpu = subroutine_cpu();
+        assert_eq!(
+            hexdump(&mut cpu, 0x8000, 18),
+            "8000  20 08 80 A2 02 00 EA EA A9 01 85 10 60 00 00 00\n8010  00 00"
+        );
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut cpu = subroutine_cpu();
+        cpu.register_a = 0x80;
+        cpu.status = StatusFlags::NEGATIVE
+            | StatusFlags::UNUSED
+            | StatusFlags::BREAK
+            | StatusFlags::INTERRUPT
+            | StatusFlags::CARRY;
+
+        assert_eq!(
+            registers(&cpu),
+            "A:80 X:00 Y:00 SP:FF PC:8000 CYC:7\nP:B5 NvUBdIzC"
+        );
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut cpu = subroutine_cpu();
+        let mut debugger = Debugger::new();
+        let mut output = vec![];
+
+        debugger
+            .repl(
+                &mut cpu,
+                "next\n\nregs\nquit\nstep\n".as_bytes(),
+                &mut output,
+            )
+            .unwrap();
+
+        // The empty line repeats next, and nothing runs after quit
+        assert_eq!(cpu.program_counter, 0x8005);
+        assert_eq!(cpu.register_x, 0x02);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("A:01 X:02"));
+    }
+}
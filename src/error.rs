@@ -0,0 +1,98 @@
+use std::fmt;
+
+/// Everything that can go wrong while loading a ROM or running the machine.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EmuError {
+    /// The file does not start with "NES\x1A"
+    BadMagic,
+    /// The file ends inside the 16 byte header
+    TruncatedHeader,
+    TruncatedPrg {
+        expected: usize,
+        actual: usize,
+    },
+    TruncatedChr {
+        expected: usize,
+        actual: usize,
+    },
+    UnsupportedMapper(u16),
+    UnsupportedFormat(String),
+    /// The CPU fetched an opcode it can't execute
+    UnknownOpcode {
+        opcode: u8,
+        pc: u16,
+    },
+    /// An unstable opcode ran while they are trapped
+    UnstableOpcode {
+        opcode: u8,
+        pc: u16,
+    },
+    /// The CPU wrote to ROM on a board that has no registers there
+    UnhandledRomWrite {
+        addr: u16,
+        data: u8,
+    },
+    /// Reading or writing a file failed
+    Io(String),
+    /// The save state is damaged or not a save state at all
+    InvalidSaveState(String),
+    UnsupportedSaveStateVersion(u16),
+    /// The save state was made with a different ROM
+    SaveStateRomMismatch,
+    /// The .sav file does not fit the cartridge PRG-RAM
+    BatterySaveSizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::BadMagic => write!(f, "File is not in iNES file format"),
+            EmuError::TruncatedHeader => write!(f, "File is too short for an iNES header"),
+            EmuError::TruncatedPrg { expected, actual } => write!(
+                f,
+                "PRG-ROM is truncated: expected {} bytes, found {}",
+                expected, actual
+            ),
+            EmuError::TruncatedChr { expected, actual } => write!(
+                f,
+                "CHR-ROM is truncated: expected {} bytes, found {}",
+                expected, actual
+            ),
+            EmuError::UnsupportedMapper(mapper) => write!(f, "Mapper {} is not supported", mapper),
+            EmuError::UnsupportedFormat(reason) => write!(f, "Unsupported ROM: {}", reason),
+            EmuError::UnknownOpcode { opcode, pc } => {
+                write!(f, "Unknown opcode {:02x} at {:04x}", opcode, pc)
+            }
+            EmuError::UnstableOpcode { opcode, pc } => {
+                write!(f, "Unstable opcode {:02x} at {:04x}", opcode, pc)
+            }
+            EmuError::UnhandledRomWrite { addr, data } => write!(
+                f,
+                "Write of {:02x} to cartridge ROM at {:04x} is not handled by the mapper",
+                data, addr
+            ),
+            EmuError::Io(reason) => write!(f, "I/O error: {}", reason),
+            EmuError::InvalidSaveState(reason) => write!(f, "Invalid save state: {}", reason),
+            EmuError::UnsupportedSaveStateVersion(version) => {
+                write!(f, "Save state version {} is not supported", version)
+            }
+            EmuError::SaveStateRomMismatch => write!(f, "Save state was made with another ROM"),
+            EmuError::BatterySaveSizeMismatch { expected, actual } => write!(
+                f,
+                "Battery save is {} bytes, the cartridge has {} bytes of PRG-RAM",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
+impl From<std::io::Error> for EmuError {
+    fn from(err: std::io::Error) -> Self {
+        EmuError::Io(err.to_string())
+    }
+}
@@ -0,0 +1,324 @@
+use std::path::Path;
+
+use crate::{
+    apu::{apu::ChannelOutput, mixer, sound::SoundPipeline, wav},
+    cpu::cpu::CPU,
+    error::EmuError,
+};
+
+const WAV_SAMPLE_RATE: u32 = 48_000;
+
+/// Names of the per channel stems, in `ChannelOutput` order
+pub const STEMS: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+
+/// Runs without any window until `frames` frames are complete, calling
+/// `on_frame` after each one. Returns the number of frames run, which is
+/// lower when the CPU halts first.
+pub fn run_frames<F>(cpu: &mut CPU, frames: usize, mut on_frame: F) -> Result<usize, EmuError>
+where
+    F: FnMut(&mut CPU),
+{
+    let mut completed = 0;
+    while completed < frames {
+        if !cpu.step()? {
+            break;
+        }
+        if cpu.bus.poll_frame_complete() {
+            completed += 1;
+            on_frame(cpu);
+        }
+    }
+    Ok(completed)
+}
+
+// Keeps a single channel of the output, as if the others were muted
+fn solo(output: ChannelOutput, stem: usize) -> ChannelOutput {
+    let mut solo = ChannelOutput::default();
+    match stem {
+        0 => solo.pulse1 = output.pulse1,
+        1 => solo.pulse2 = output.pulse2,
+        2 => solo.triangle = output.triangle,
+        3 => solo.noise = output.noise,
+        _ => solo.dmc = output.dmc,
+    }
+    solo
+}
+
+/// Mixes the APU output into a WAV file, and optionally every channel into its own.
+pub struct WavRecorder {
+    mix: SoundPipeline,
+    mix_samples: Vec<f32>,
+    // Empty unless stems were asked for
+    stems: Vec<(SoundPipeline, Vec<f32>)>,
+}
+
+impl WavRecorder {
+    pub fn new(stems: bool) -> Self {
+        let stem_count = if stems { STEMS.len() } else { 0 };
+        WavRecorder {
+            mix: SoundPipeline::new(WAV_SAMPLE_RATE),
+            mix_samples: vec![],
+            stems: (0..stem_count)
+                .map(|_| (SoundPipeline::new(WAV_SAMPLE_RATE), vec![]))
+                .collect(),
+        }
+    }
+
+    pub fn push(&mut self, output: &[ChannelOutput]) {
+        for &channels in output {
+            self.mix.push(mixer::mix(channels));
+            for (stem, (pipeline, _)) in self.stems.iter_mut().enumerate() {
+                pipeline.push(mixer::mix(solo(channels, stem)));
+            }
+        }
+        self.mix.read_samples(&mut self.mix_samples);
+        for (pipeline, samples) in &mut self.stems {
+            pipeline.read_samples(samples);
+        }
+    }
+
+    /// Writes the mix to `path` and the stems next to it, e.g. `music.pulse1.wav`.
+    pub fn write(&self, path: &Path) -> Result<(), EmuError> {
+        wav::write(path, WAV_SAMPLE_RATE, &self.mix_samples)?;
+        for (stem, (_, samples)) in self.stems.iter().enumerate() {
+            let stem_path = path.with_extension(format!("{}.wav", STEMS[stem]));
+            wav::write(&stem_path, WAV_SAMPLE_RATE, samples)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `frames` frames and saves what the APU played, for checking music
+/// engines without a sound card.
+pub fn export_wav(cpu: &mut CPU, frames: usize, path: &Path, stems: bool) -> Result<(), EmuError> {
+    let mut recorder = WavRecorder::new(stems);
+    cpu.bus.apu.collect_samples = true;
+    run_frames(cpu, frames, |cpu| {
+        recorder.push(&cpu.bus.apu.take_samples())
+    })?;
+    recorder.write(path)
+}
+
+// Result protocol of blargg's test ROMs, in PRG-RAM
+const TEST_STATUS: u16 = 0x6000;
+const TEST_SIGNATURE: u16 = 0x6001;
+const TEST_TEXT: u16 = 0x6004;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET_REQUESTED: u8 = 0x81;
+// The ROM asks for the reset button to be held at least 100ms later
+const RESET_DELAY_FRAMES: usize = 6;
+
+#[derive(Debug, PartialEq)]
+pub enum TestStatus {
+    Passed,
+    /// The result code the ROM reported
+    Failed(u8),
+    /// No result before the frame or cycle limit
+    TimedOut,
+    /// The CPU stopped before reporting a result
+    Halted,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TestReport {
+    pub status: TestStatus,
+    /// Text the ROM wrote from $6004 on
+    pub text: String,
+    pub frames: usize,
+}
+
+fn has_signature(cpu: &CPU) -> bool {
+    (0..3).all(|i| cpu.bus.peek(TEST_SIGNATURE + i) == SIGNATURE[i as usize])
+}
+
+fn read_text(cpu: &CPU) -> String {
+    let bytes: Vec<u8> = (TEST_TEXT..=0x7fff)
+        .map(|addr| cpu.bus.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Runs a test ROM until it reports its result at $6000, pressing reset when
+/// it asks for it, or until `max_frames` frames or `max_cycles` CPU cycles.
+pub fn run_test_rom(
+    cpu: &mut CPU,
+    max_frames: usize,
+    max_cycles: usize,
+) -> Result<TestReport, EmuError> {
+    let mut frames = 0;
+    let mut reset_at = None;
+    let report = |cpu: &CPU, status, frames| TestReport {
+        status,
+        text: read_text(cpu),
+        frames,
+    };
+
+    while frames < max_frames && cpu.cycles < max_cycles {
+        if !cpu.step()? {
+            return Ok(report(cpu, TestStatus::Halted, frames));
+        }
+        if !cpu.bus.poll_frame_complete() {
+            continue;
+        }
+        frames += 1;
+
+        if !has_signature(cpu) {
+            continue;
+        }
+        match cpu.bus.peek(TEST_STATUS) {
+            STATUS_RUNNING => {}
+            STATUS_RESET_REQUESTED => match reset_at {
+                None => reset_at = Some(frames + RESET_DELAY_FRAMES),
+                Some(frame) if frame <= frames => {
+                    reset_at = None;
+                    cpu.reset();
+                }
+                Some(_) => {}
+            },
+            0 => return Ok(report(cpu, TestStatus::Passed, frames)),
+            code => return Ok(report(cpu, TestStatus::Failed(code), frames)),
+        }
+    }
+    Ok(report(cpu, TestStatus::TimedOut, frames))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bus::Bus, rom::Rom};
+
+    // Plays a 440 Hz square on pulse 1 forever
+    fn pulse_cpu() -> CPU {
+        let code = vec![
+            0xa9, 0x01, 0x8d, 0x15, 0x40, // LDA #$01, STA $4015
+            0xa9, 0x9f, 0x8d, 0x00, 0x40, // LDA #$9F, STA $4000
+            0xa9, 0xfd, 0x8d, 0x02, 0x40, // LDA #$FD, STA $4002
+            0xa9, 0x08, 0x8d, 0x03, 0x40, // LDA #$08, STA $4003
+            0x4c, 0x14, 0x80, // JMP $8014
+        ];
+        let mut cpu = CPU::new(Bus::new(Rom::from_test_code(code)).unwrap());
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let mut cpu = pulse_cpu();
+        let mut seen = 0;
+
+        assert_eq!(run_frames(&mut cpu, 3, |_| seen += 1), Ok(3));
+        assert_eq!(seen, 3);
+        // A frame is 29780.5 CPU cycles
+        assert!((89_000..=90_000).contains(&cpu.cycles));
+    }
+
+    #[test]
+    fn test_recorder_stems() {
+        let mut cpu = pulse_cpu();
+        let mut recorder = WavRecorder::new(true);
+        cpu.bus.apu.collect_samples = true;
+        run_frames(&mut cpu, 10, |cpu| {
+            recorder.push(&cpu.bus.apu.take_samples())
+        })
+        .unwrap();
+
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        // 10 frames at 60.1 Hz
+        assert!((7_900..8_000).contains(&recorder.mix_samples.len()));
+        assert!(peak(&recorder.mix_samples) > 0.05);
+        assert!(peak(&recorder.stems[0].1) > 0.05);
+        assert_eq!(recorder.stems[1].1.len(), recorder.mix_samples.len());
+        assert_eq!(peak(&recorder.stems[1].1), 0.0);
+    }
+
+    fn store(code: &mut Vec<u8>, addr: u16, value: u8) {
+        code.extend([0xa9, value, 0x8d, addr as u8, (addr >> 8) as u8]);
+    }
+
+    fn store_signature(code: &mut Vec<u8>) {
+        for (i, byte) in SIGNATURE.iter().enumerate() {
+            store(code, TEST_SIGNATURE + i as u16, *byte);
+        }
+    }
+
+    // JMP to itself
+    fn spin(code: &mut Vec<u8>) {
+        let here = 0x8000 + code.len() as u16;
+        code.extend([0x4c, here as u8, (here >> 8) as u8]);
+    }
+
+    fn test_cpu(code: Vec<u8>) -> CPU {
+        let mut cpu = CPU::new(Bus::new(Rom::from_test_code(code)).unwrap());
+        cpu.reset();
+        cpu
+    }
+
+    fn reporting_rom(status: u8, text: &str) -> Vec<u8> {
+        let mut code = vec![];
+        for (i, byte) in text.bytes().chain([0]).enumerate() {
+            store(&mut code, TEST_TEXT + i as u16, byte);
+        }
+        store_signature(&mut code);
+        store(&mut code, TEST_STATUS, status);
+        spin(&mut code);
+        code
+    }
+
+    #[test]
+    fn test_passing_rom() {
+        let mut cpu = test_cpu(reporting_rom(0, "Passed\n"));
+        let report = run_test_rom(&mut cpu, 60, usize::MAX).unwrap();
+
+        assert_eq!(report.status, TestStatus::Passed);
+        assert_eq!(report.text, "Passed\n");
+        assert_eq!(report.frames, 1);
+    }
+
+    #[test]
+    fn test_failing_rom() {
+        let mut cpu = test_cpu(reporting_rom(3, "Failed #3"));
+        let report = run_test_rom(&mut cpu, 60, usize::MAX).unwrap();
+
+        assert_eq!(report.status, TestStatus::Failed(3));
+        assert_eq!(report.text, "Failed #3");
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut code = vec![];
+        store_signature(&mut code);
+        store(&mut code, TEST_STATUS, STATUS_RUNNING);
+        spin(&mut code);
+
+        let report = run_test_rom(&mut test_cpu(code.clone()), 10, usize::MAX).unwrap();
+        assert_eq!(report.status, TestStatus::TimedOut);
+        assert_eq!(report.frames, 10);
+
+        let report = run_test_rom(&mut test_cpu(code), usize::MAX, 100_000).unwrap();
+        assert_eq!(report.status, TestStatus::TimedOut);
+        assert_eq!(report.frames, 3);
+    }
+
+    #[test]
+    fn test_reset_request() {
+        // The first boot asks for a reset, the second one passes
+        // Inhibit the APU frame IRQ first, reset doesn't mask it
+        let mut code = vec![];
+        store(&mut code, 0x4017, 0x40);
+        code.extend([0xa5, 0x10, 0xd0, 0x00, 0xe6, 0x10]); // LDA $10, BNE second, INC $10
+        store_signature(&mut code);
+        store(&mut code, TEST_STATUS, STATUS_RESET_REQUESTED);
+        spin(&mut code);
+        code[8] = (code.len() - 9) as u8;
+        store(&mut code, TEST_STATUS, 0);
+        spin(&mut code);
+
+        let mut cpu = test_cpu(code);
+        let report = run_test_rom(&mut cpu, 60, usize::MAX).unwrap();
+
+        assert_eq!(report.status, TestStatus::Passed);
+        assert_eq!(report.frames, 1 + RESET_DELAY_FRAMES + 1);
+    }
+}
@@ -0,0 +1,167 @@
+use bitflags::bitflags;
+
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+bitflags! {
+    /// # Standard controller https://www.nesdev.org/wiki/Standard_controller
+    ///
+    /// Buttons in the order they are shifted out, one bit per read:
+    ///
+    ///  7 6 5 4 3 2 1 0
+    ///  R L D U T S B A
+    ///  | | | | | | | +--- A
+    ///  | | | | | | +----- B
+    ///  | | | | | +------- Select
+    ///  | | | | +--------- Start
+    ///  | | | +----------- Up
+    ///  | | +------------- Down
+    ///  | +--------------- Left
+    ///  +----------------- Right
+    ///
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+    pub struct JoypadButton: u8 {
+        const BUTTON_A = 0b0000_0001;
+        const BUTTON_B = 0b0000_0010;
+        const SELECT   = 0b0000_0100;
+        const START    = 0b0000_1000;
+        const UP       = 0b0001_0000;
+        const DOWN     = 0b0010_0000;
+        const LEFT     = 0b0100_0000;
+        const RIGHT    = 0b1000_0000;
+    }
+}
+
+// Only bit 0 is driven, the rest keep the high byte of $4016/$4017 left on the data bus
+const OPEN_BUS: u8 = 0x40;
+
+#[derive(Default)]
+pub struct Joypad {
+    // While set the shift register keeps reloading, so reads return A
+    strobe: bool,
+    button_index: u8,
+    pub button_status: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: JoypadButton::from_bits_truncate(0),
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let data = self.peek();
+        if !self.strobe && self.button_index <= 7 {
+            self.button_index += 1;
+        }
+        data
+    }
+
+    /// Like `read` without shifting, used by debugging tools.
+    pub fn peek(&self) -> u8 {
+        // Official controllers return 1 after all eight buttons
+        let bit = if self.button_index > 7 {
+            1
+        } else {
+            (self.button_status.bits() >> self.button_index) & 1
+        };
+        bit | OPEN_BUS
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+}
+
+// The buttons are live input from the frontend, a restored state keeps
+// whatever the player is holding now
+impl Savestate for Joypad {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.strobe);
+        w.u8(self.button_index);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.strobe = r.bool()?;
+        self.button_index = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_keeps_held_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        joypad.read();
+        let mut w = StateWriter::new();
+        joypad.save(&mut w);
+        let state = w.into_bytes();
+
+        let mut restored = Joypad::new();
+        restored.set_button_pressed_status(JoypadButton::RIGHT, true);
+        restored.load(&mut StateReader::new(&state)).unwrap();
+
+        assert_eq!(restored.button_status, JoypadButton::RIGHT);
+        assert_eq!(restored.button_index, 1);
+    }
+
+    #[test]
+    fn test_strobe_mode() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        for _ in 0..10 {
+            assert_eq!(joypad.read() & 1, 1);
+        }
+    }
+
+    #[test]
+    fn test_strobe_mode_on_off() {
+        let mut joypad = Joypad::new();
+
+        joypad.write(0);
+        joypad.set_button_pressed_status(JoypadButton::RIGHT, true);
+        joypad.set_button_pressed_status(JoypadButton::LEFT, true);
+        joypad.set_button_pressed_status(JoypadButton::SELECT, true);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+
+        for _ in 0..=1 {
+            assert_eq!(joypad.read() & 1, 0);
+            assert_eq!(joypad.read() & 1, 1);
+            assert_eq!(joypad.read() & 1, 1);
+            assert_eq!(joypad.read() & 1, 0);
+            assert_eq!(joypad.read() & 1, 0);
+            assert_eq!(joypad.read() & 1, 0);
+            assert_eq!(joypad.read() & 1, 1);
+            assert_eq!(joypad.read() & 1, 1);
+
+            for _ in 0..10 {
+                assert_eq!(joypad.read() & 1, 1);
+            }
+            joypad.write(1);
+            joypad.write(0);
+        }
+    }
+
+    #[test]
+    fn test_upper_bits_are_open_bus() {
+        let mut joypad = Joypad::new();
+        assert_eq!(joypad.read(), 0x40);
+    }
+}
@@ -1,64 +1,357 @@
 use std::env;
 use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
 
+use battery::BatterySave;
 use bus::Bus;
 use cpu::cpu::CPU;
-use cpu::memory::Mem;
-use rand::Rng;
+use cpu::disasm::Disassembly;
+use cpu::trace::trace;
+use debugger::Debugger;
+use error::EmuError;
+use headless::TestStatus;
+use ppu::frame;
+use rewind::Rewind;
 use rom::Rom;
-use sdl::sdl::{handle_user_input, read_screen_state};
+use sdl::audio::Audio;
+use sdl::sdl::{Hotkey, handle_user_input};
 use sdl2::pixels::PixelFormatEnum;
 
+pub mod apu;
+pub mod battery;
 pub mod bus;
 pub mod cpu;
+pub mod debugger;
+pub mod error;
+pub mod headless;
+pub mod joypad;
+pub mod mapper;
+pub mod ppu;
+pub mod rewind;
 pub mod rom;
+pub mod savestate;
 pub mod sdl;
 
-fn main() {
-    const WINDOW_HEIGHT: u32 = (32.0 * 10.0) as u32;
-    const WINDOW_WIDTH: u32 = (32.0 * 10.0) as u32;
+const USAGE: &str = "usage: nes_emulator <rom> [--trace] [--rewind-mb <n>]
+       nes_emulator wav <rom> <out.wav> [--frames <n>] [--stems]
+       nes_emulator test <rom> [--frames <n>] [--cycles <n>]
+       nes_emulator debug <rom>
+       nes_emulator disasm <rom> [--bank <n>] [--origin <addr>] [--entry <addr>]";
 
-    // Load the game
+fn main() {
     let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("wav") => wav_command(&args[2..]),
+        Some("test") => test_command(&args[2..]),
+        Some("debug") => debug_command(&args[2..]),
+        Some("disasm") => disasm_command(&args[2..]),
+        Some(rom_path) => run_window(rom_path, &args[2..]),
+        None => exit_with_usage(),
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn load_rom(file_path: &str) -> Rom {
+    let game_file: Vec<u8> = fs::read(file_path).unwrap_or_else(|err| {
+        eprintln!("Could not read {}: {}", file_path, err);
+        process::exit(1);
+    });
+    Rom::new(&game_file).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", file_path, err);
+        process::exit(1);
+    })
+}
 
-    let file_path = &args[1];
-    let game_file: Vec<u8> = fs::read(file_path).unwrap();
-    let rom = Rom::new(&game_file).unwrap();
-    let bus = Bus::new(rom);
+fn load_cpu(file_path: &str) -> CPU {
+    let bus = Bus::new(load_rom(file_path)).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", file_path, err);
+        process::exit(1);
+    });
     let mut cpu = CPU::new(bus);
     cpu.reset();
+    cpu
+}
+
+/// Value of `--name <value>` in the options, if present
+fn option_value<'a>(options: &'a [String], name: &str) -> Option<&'a str> {
+    let index = options.iter().position(|option| option == name)?;
+    match options.get(index + 1) {
+        Some(value) => Some(value),
+        None => exit_with_usage(),
+    }
+}
+
+fn number_option(options: &[String], name: &str) -> Option<usize> {
+    option_value(options, name).map(|value| value.parse().unwrap_or_else(|_| exit_with_usage()))
+}
+
+/// Value of `--name <addr>` in the options, in hex
+fn address_option(options: &[String], name: &str) -> Option<u16> {
+    option_value(options, name)
+        .map(|value| debugger::parse_hex(value).unwrap_or_else(|_| exit_with_usage()))
+}
+
+/// Records the audio of the first frames of a ROM, without opening any SDL device.
+fn wav_command(args: &[String]) {
+    const DEFAULT_FRAMES: usize = 600;
+
+    let (rom_path, wav_path) = match args {
+        [rom_path, wav_path, ..] => (rom_path, Path::new(wav_path)),
+        _ => exit_with_usage(),
+    };
+    let frames = number_option(args, "--frames").unwrap_or(DEFAULT_FRAMES);
+    let stems = args.iter().any(|arg| arg == "--stems");
+
+    let mut cpu = load_cpu(rom_path);
+    if let Err(err) = headless::export_wav(&mut cpu, frames, wav_path, stems) {
+        eprintln!("Could not export {}: {}", wav_path.display(), err);
+        process::exit(1);
+    }
+}
+
+/// Runs a test ROM without a window and exits with 0 only if it reports a pass.
+fn test_command(args: &[String]) {
+    // Enough for the slowest of blargg's suites
+    const DEFAULT_FRAMES: usize = 60 * 60;
+
+    let rom_path = match args.first() {
+        Some(rom_path) => rom_path,
+        None => exit_with_usage(),
+    };
+    let frames = number_option(args, "--frames").unwrap_or(DEFAULT_FRAMES);
+    let cycles = number_option(args, "--cycles").unwrap_or(usize::MAX);
+
+    let mut cpu = load_cpu(rom_path);
+    let report = headless::run_test_rom(&mut cpu, frames, cycles).unwrap_or_else(|err| {
+        eprintln!("Emulation stopped: {}", err);
+        process::exit(1);
+    });
+
+    print!("{}", report.text);
+    match report.status {
+        TestStatus::Passed => println!("Passed after {} frames", report.frames),
+        TestStatus::Failed(code) => println!("Failed with code {}", code),
+        TestStatus::TimedOut => println!("No result after {} frames", report.frames),
+        TestStatus::Halted => println!("CPU halted after {} frames", report.frames),
+    }
+    if report.status != TestStatus::Passed {
+        process::exit(1);
+    }
+}
+
+/// Writes battery backed PRG-RAM to its .sav file if it changed.
+fn flush_battery(cpu: &CPU, battery: &mut Option<BatterySave>) {
+    let Some(battery) = battery else { return };
+    if let Err(err) = battery.flush(&cpu.bus) {
+        eprintln!("Could not save {}: {}", battery.path().display(), err);
+    }
+}
+
+fn quit(cpu: &CPU, battery: &mut Option<BatterySave>) -> ! {
+    flush_battery(cpu, battery);
+    process::exit(0);
+}
+
+/// Steps through a ROM from the terminal, see `help` at the prompt for the commands.
+fn debug_command(args: &[String]) {
+    let rom_path = match args.first() {
+        Some(rom_path) => rom_path,
+        None => exit_with_usage(),
+    };
+
+    let mut cpu = load_cpu(rom_path);
+    let stdin = io::stdin();
+    if let Err(err) = Debugger::new().repl(&mut cpu, stdin.lock(), &mut io::stdout()) {
+        eprintln!("Debugger stopped: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Lists a 16KiB bank of PRG-ROM, by default the last one, which holds the
+/// vectors on most boards.
+fn disasm_command(args: &[String]) {
+    const BANK_SIZE: usize = 0x4000;
+
+    let rom_path = match args.first() {
+        Some(rom_path) => rom_path,
+        None => exit_with_usage(),
+    };
+    let rom = load_rom(rom_path);
+    let bank_count = rom.prg_rom.chunks(BANK_SIZE).count();
+    let bank = number_option(args, "--bank").unwrap_or(bank_count.saturating_sub(1));
+    let data = rom.prg_rom.chunks(BANK_SIZE).nth(bank).unwrap_or_else(|| {
+        eprintln!("{} has {} PRG banks", rom_path, bank_count);
+        process::exit(1);
+    });
+
+    // The last bank is usually fixed at $C000, the others get switched in at $8000
+    let default_origin = if bank + 1 == bank_count {
+        0xC000
+    } else {
+        0x8000
+    };
+    let origin = address_option(args, "--origin").unwrap_or(default_origin);
+    if origin as usize + data.len() > 0x10000 {
+        eprintln!("Bank {} does not fit at ${:04X}", bank, origin);
+        process::exit(1);
+    }
+    let entries: Vec<u16> = address_option(args, "--entry").into_iter().collect();
+
+    println!("; {} bank {} at ${:04X}", rom_path, bank, origin);
+    println!("{}", Disassembly::new(data, origin, &entries).listing());
+}
+
+/// Saves to or loads from the numbered state files next to the ROM, starts
+/// and stops rewinding, or quits.
+fn handle_hotkey(
+    cpu: &mut CPU,
+    rom_path: &str,
+    slot: &mut u8,
+    rewinding: &mut bool,
+    battery: &mut Option<BatterySave>,
+    hotkey: Hotkey,
+) {
+    let path = savestate::slot_path(Path::new(rom_path), *slot);
+    match hotkey {
+        Hotkey::Quit => quit(cpu, battery),
+        Hotkey::Rewind(held) => *rewinding = held,
+        Hotkey::SelectSlot(new_slot) => {
+            *slot = new_slot;
+            println!("Save slot {}", new_slot);
+        }
+        Hotkey::SaveState => match fs::write(&path, cpu.save_state()) {
+            Ok(()) => println!("Saved state to {}", path.display()),
+            Err(err) => eprintln!("Could not save {}: {}", path.display(), err),
+        },
+        Hotkey::LoadState => {
+            let result = fs::read(&path)
+                .map_err(EmuError::from)
+                .and_then(|data| cpu.load_state(&data));
+            match result {
+                Ok(()) => println!("Loaded state from {}", path.display()),
+                Err(err) => eprintln!("Could not load {}: {}", path.display(), err),
+            }
+        }
+    }
+}
+
+fn run_window(file_path: &str, options: &[String]) {
+    const SCALE: u32 = 3;
+    const DEFAULT_REWIND_MB: usize = 64;
+    // One second of frames between rewind keyframes
+    const REWIND_KEYFRAME_INTERVAL: usize = 60;
+    // Write battery saves every 5 seconds, so a crash loses little progress
+    const BATTERY_FLUSH_INTERVAL: usize = 300;
+
+    // Print a nestest style line for every instruction
+    let trace_enabled = options.iter().any(|option| option == "--trace");
+    let mut cpu = load_cpu(file_path);
+    let sav_path = battery::sav_path(Path::new(file_path));
+    let mut battery = BatterySave::open(&mut cpu.bus, sav_path.clone()).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", sav_path.display(), err);
+        process::exit(1);
+    });
 
     // Init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
-        .window("Snake game", WINDOW_WIDTH, WINDOW_HEIGHT)
+        .window(
+            "NES",
+            frame::WIDTH as u32 * SCALE,
+            frame::HEIGHT as u32 * SCALE,
+        )
         .position_centered()
         .build()
         .unwrap();
 
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
+    let mut audio = Audio::open(&sdl_context.audio().unwrap())
+        .map_err(|err| eprintln!("Audio disabled: {}", err))
+        .ok();
+    cpu.bus.apu.collect_samples = audio.is_some();
 
     let creator = canvas.texture_creator();
     let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 32, 32)
+        .create_texture_target(
+            PixelFormatEnum::RGB24,
+            frame::WIDTH as u32,
+            frame::HEIGHT as u32,
+        )
         .unwrap();
 
-    let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng = rand::rng();
-
-    cpu.run_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
-        cpu.mem_write(0xfe, rng.random_range(1..16));
+    let rewind_budget = number_option(options, "--rewind-mb").unwrap_or(DEFAULT_REWIND_MB);
+    // A budget too large to count in bytes just means no limit
+    let mut rewind = Rewind::new(
+        rewind_budget.saturating_mul(1024 * 1024),
+        REWIND_KEYFRAME_INTERVAL,
+    );
+    let mut rewinding = false;
+    let mut slot = 1;
+    let mut frames = 0;
+    let result = cpu.run_with_callback(|cpu| {
+        if trace_enabled {
+            println!("{}", trace(cpu));
+        }
 
-        if read_screen_state(cpu, &mut screen_state) {
-            texture.update(None, &screen_state, 32 * 3).unwrap();
+        if cpu.bus.poll_frame_complete() {
+            texture
+                .update(None, &cpu.bus.ppu.frame.data, frame::WIDTH * 3)
+                .unwrap();
             canvas.copy(&texture, None, None).unwrap();
             canvas.present();
-        }
+            // Backwards audio is just noise, it is dropped while rewinding
+            let samples = cpu.bus.apu.take_samples();
+            if let (Some(audio), false) = (&mut audio, rewinding) {
+                audio.queue(&samples);
+            }
+
+            let hotkeys =
+                handle_user_input(&mut event_pump, &mut cpu.bus.joypad1, &mut cpu.bus.joypad2);
+            for hotkey in hotkeys {
+                handle_hotkey(
+                    cpu,
+                    file_path,
+                    &mut slot,
+                    &mut rewinding,
+                    &mut battery,
+                    hotkey,
+                );
+            }
+
+            // A restored state goes on to draw the frame after it, so each frame
+            // shown while rewinding is one earlier than the last
+            if !rewinding {
+                rewind.push(&cpu.save_state());
+            } else if let Some(Err(err)) = rewind.pop().map(|state| cpu.load_state(&state)) {
+                eprintln!("Could not rewind: {}", err);
+            }
 
-        ::std::thread::sleep(std::time::Duration::new(0, 70_00));
+            frames += 1;
+            if frames % BATTERY_FLUSH_INTERVAL == 0 {
+                flush_battery(cpu, &mut battery);
+            }
+        }
     });
+
+    // Keep the last frame on screen until the window is closed
+    if let Err(err) = result {
+        eprintln!("Emulation stopped: {}", err);
+    }
+    loop {
+        // Other hotkeys are ignored, the machine can't go on
+        let hotkeys =
+            handle_user_input(&mut event_pump, &mut cpu.bus.joypad1, &mut cpu.bus.joypad2);
+        if hotkeys.contains(&Hotkey::Quit) {
+            quit(&cpu, &mut battery);
+        }
+        ::std::thread::sleep(std::time::Duration::from_millis(16));
+    }
 }
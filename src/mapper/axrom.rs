@@ -0,0 +1,130 @@
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::mapper::{ChrMemory, Mapper, bus_conflict};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// Mapper 7 (AxROM) https://www.nesdev.org/wiki/AxROM
+///
+/// Writes to $8000-$FFFF select the 32KiB PRG bank (bits 0-2) and
+/// which 1KiB of VRAM is shown on every nametable (bit 4).
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    bank: usize,
+    upper_screen: bool,
+    // Only AMROM boards have them
+    pub bus_conflicts: bool,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom) -> Self {
+        Axrom {
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            bank: 0,
+            upper_screen: false,
+            // NES 2.0 submapper 2 marks an AMROM board
+            bus_conflicts: rom.submapper == 2,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+}
+
+impl Mapper for Axrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let offset = (self.bank % self.bank_count()) * PRG_BANK_SIZE;
+                let addr = offset + (addr as usize - 0x8000);
+                self.prg_rom[addr % self.prg_rom.len()]
+            }
+            _ => {
+                println!("Ignoring mem access at {}", addr);
+                0
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => {
+                let data = if self.bus_conflicts {
+                    bus_conflict(self, addr, data)
+                } else {
+                    data
+                };
+                self.bank = (data & 0b0111) as usize;
+                self.upper_screen = data & 0b1_0000 != 0;
+            }
+            _ => println!("Ignoring mem write-access at {}", addr),
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.upper_screen {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::SingleScreenLower
+        }
+    }
+}
+
+impl Savestate for Axrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.chr.save(w);
+        w.usize(self.bank);
+        w.bool(self.upper_screen);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.chr.load(r)?;
+        self.bank = r.usize()?;
+        self.upper_screen = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::mapper::banked_test_rom;
+
+    #[test]
+    fn test_switches_32k_bank() {
+        let mut mapper = Axrom::new(banked_test_rom(7, 4, PRG_BANK_SIZE, 0, 0));
+
+        assert_eq!(mapper.read_prg(0xFFFF), 0);
+
+        mapper.write_prg(0x8000, 2);
+
+        assert_eq!(mapper.read_prg(0x8000), 2);
+        assert_eq!(mapper.read_prg(0xFFFF), 2);
+    }
+
+    #[test]
+    fn test_single_screen_select() {
+        let mut mapper = Axrom::new(banked_test_rom(7, 4, PRG_BANK_SIZE, 0, 0));
+
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+
+        mapper.write_prg(0x8000, 0b1_0000);
+
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
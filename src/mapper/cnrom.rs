@@ -0,0 +1,120 @@
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::mapper::{ChrMemory, Mapper, bus_conflict};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 3 (CNROM) https://www.nesdev.org/wiki/CNROM
+///
+/// PRG is fixed like NROM, writes to $8000-$FFFF select the 8KiB CHR bank.
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    chr_bank: usize,
+    pub bus_conflicts: bool,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom) -> Self {
+        Cnrom {
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+            bus_conflicts: rom.submapper != 1,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_bank * CHR_BANK_SIZE + addr as usize
+    }
+}
+
+impl Mapper for Cnrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                // 16KiB carts are mirrored into $C000-$FFFF
+                let addr = (addr - 0x8000) as usize % self.prg_rom.len();
+                self.prg_rom[addr]
+            }
+            _ => {
+                println!("Ignoring mem access at {}", addr);
+                0
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => {
+                let data = if self.bus_conflicts {
+                    bus_conflict(self, addr, data)
+                } else {
+                    data
+                };
+                self.chr_bank = data as usize;
+            }
+            _ => println!("Ignoring mem write-access at {}", addr),
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Savestate for Cnrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.chr.save(w);
+        w.usize(self.chr_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.chr.load(r)?;
+        self.chr_bank = r.usize()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::mapper::banked_test_rom;
+
+    #[test]
+    fn test_switches_chr_bank() {
+        let mut mapper = Cnrom::new(banked_test_rom(3, 2, 0x4000, 4, CHR_BANK_SIZE));
+        mapper.bus_conflicts = false;
+
+        mapper.write_prg(0x8000, 2);
+
+        assert_eq!(mapper.read_chr(0x0000), 2);
+        assert_eq!(mapper.read_chr(0x1fff), 2);
+        assert_eq!(mapper.read_prg(0xC000), 1);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = banked_test_rom(3, 2, 0x4000, 4, CHR_BANK_SIZE);
+        rom.prg_rom[0x10] = 0b01;
+        let mut mapper = Cnrom::new(rom);
+
+        mapper.write_prg(0x8010, 0b11);
+
+        assert_eq!(mapper.read_chr(0x0000), 1);
+    }
+}
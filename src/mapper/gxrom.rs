@@ -0,0 +1,115 @@
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::mapper::{ChrMemory, Mapper, bus_conflict};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 66 (GxROM) https://www.nesdev.org/wiki/GxROM
+///
+/// Writes to $8000-$FFFF select the 32KiB PRG bank (bits 4-5)
+/// and the 8KiB CHR bank (bits 0-1).
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    prg_bank: usize,
+    chr_bank: usize,
+    pub bus_conflicts: bool,
+}
+
+impl Gxrom {
+    pub fn new(rom: Rom) -> Self {
+        Gxrom {
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+            prg_bank: 0,
+            chr_bank: 0,
+            bus_conflicts: true,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_bank * CHR_BANK_SIZE + addr as usize
+    }
+}
+
+impl Mapper for Gxrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let addr = self.prg_bank * PRG_BANK_SIZE + (addr as usize - 0x8000);
+                self.prg_rom[addr % self.prg_rom.len()]
+            }
+            _ => {
+                println!("Ignoring mem access at {}", addr);
+                0
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => {
+                let data = if self.bus_conflicts {
+                    bus_conflict(self, addr, data)
+                } else {
+                    data
+                };
+                self.prg_bank = ((data >> 4) & 0b11) as usize;
+                self.chr_bank = (data & 0b11) as usize;
+            }
+            _ => println!("Ignoring mem write-access at {}", addr),
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Savestate for Gxrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.chr.save(w);
+        w.usize(self.prg_bank);
+        w.usize(self.chr_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.chr.load(r)?;
+        self.prg_bank = r.usize()?;
+        self.chr_bank = r.usize()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::mapper::banked_test_rom;
+
+    #[test]
+    fn test_switches_prg_and_chr_banks() {
+        let mut mapper = Gxrom::new(banked_test_rom(66, 4, PRG_BANK_SIZE, 4, CHR_BANK_SIZE));
+        mapper.bus_conflicts = false;
+
+        mapper.write_prg(0x8000, 0b10_0011);
+
+        assert_eq!(mapper.read_prg(0x8000), 2);
+        assert_eq!(mapper.read_prg(0xFFFF), 2);
+        assert_eq!(mapper.read_chr(0x0000), 3);
+    }
+}
@@ -0,0 +1,157 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::{
+    axrom::Axrom, cnrom::Cnrom, gxrom::Gxrom, mmc1::Mmc1, mmc3::Mmc3, nrom::Nrom, uxrom::Uxrom,
+};
+
+const CHR_RAM_SIZE: usize = 0x2000;
+const PRG_RAM_WINDOW_SIZE: usize = 0x2000;
+
+/// The cartridge board: owns PRG/CHR banking, mirroring control and PRG-RAM.
+/// Save states cover everything but the ROM itself.
+pub trait Mapper: Savestate {
+    /// CPU read from $6000-$FFFF
+    fn read_prg(&self, addr: u16) -> u8;
+
+    /// CPU read from $6000-$FFFF for debugging tools, which must not log
+    /// anything. Boards without PRG-RAM give 0 at $6000-$7FFF.
+    fn peek_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram().is_empty() => 0,
+            _ => self.read_prg(addr),
+        }
+    }
+
+    /// CPU write to $6000-$FFFF
+    fn write_prg(&mut self, addr: u16, data: u8);
+    /// PPU read from $0000-$1FFF
+    fn read_chr(&self, addr: u16) -> u8;
+    /// PPU write to $0000-$1FFF, ignored unless the board has CHR-RAM
+    fn write_chr(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    /// Called with every address the PPU puts on its bus (pattern fetches,
+    /// PPUADDR/PPUDATA accesses), so boards can watch PPU A12.
+    fn ppu_bus_address(&mut self, _addr: u16) {}
+
+    /// Level of the cartridge IRQ line.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Hands out a fault raised by the last PRG access, if any.
+    fn take_fault(&mut self) -> Option<EmuError> {
+        None
+    }
+
+    /// PRG-RAM of the board, empty if it has none. Battery saves go through these.
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+}
+
+/// Shared between the bus (PRG side) and the PPU (CHR side).
+pub type SharedMapper = Rc<RefCell<dyn Mapper>>;
+
+pub fn from_rom(rom: Rom) -> Result<SharedMapper, EmuError> {
+    match rom.mapper {
+        0 => Ok(Rc::new(RefCell::new(Nrom::new(rom)))),
+        1 => Ok(Rc::new(RefCell::new(Mmc1::new(rom)))),
+        2 => Ok(Rc::new(RefCell::new(Uxrom::new(rom)))),
+        3 => Ok(Rc::new(RefCell::new(Cnrom::new(rom)))),
+        4 => Ok(Rc::new(RefCell::new(Mmc3::new(rom)))),
+        7 => Ok(Rc::new(RefCell::new(Axrom::new(rom)))),
+        66 => Ok(Rc::new(RefCell::new(Gxrom::new(rom)))),
+        _ => Err(EmuError::UnsupportedMapper(rom.mapper)),
+    }
+}
+
+/// PRG-RAM asked for by the header, boards map at most 8KiB of it at $6000-$7FFF.
+pub fn prg_ram_size(rom: &Rom) -> usize {
+    (rom.prg_ram_size + rom.prg_nvram_size).min(PRG_RAM_WINDOW_SIZE)
+}
+
+/// CHR memory of the board, boards without CHR-ROM carry 8KiB of CHR-RAM instead.
+pub struct ChrMemory {
+    pub data: Vec<u8>,
+    pub is_ram: bool,
+}
+
+impl ChrMemory {
+    pub fn new(chr_rom: Vec<u8>) -> Self {
+        if chr_rom.is_empty() {
+            ChrMemory {
+                data: vec![0; CHR_RAM_SIZE],
+                is_ram: true,
+            }
+        } else {
+            ChrMemory {
+                data: chr_rom,
+                is_ram: false,
+            }
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        self.data[addr % self.data.len()]
+    }
+
+    pub fn write(&mut self, addr: usize, data: u8) {
+        if self.is_ram {
+            let len = self.data.len();
+            self.data[addr % len] = data;
+        }
+    }
+}
+
+// CHR-ROM comes back with the ROM, only CHR-RAM is saved
+impl Savestate for ChrMemory {
+    fn save(&self, w: &mut StateWriter) {
+        if self.is_ram {
+            w.bytes(&self.data);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        if self.is_ram {
+            r.bytes(&mut self.data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Boards with bus conflicts drive the ROM output while the CPU writes,
+/// so only bits that are set in both reach the latch.
+pub fn bus_conflict(mapper: &dyn Mapper, addr: u16, data: u8) -> u8 {
+    data & mapper.read_prg(addr)
+}
+
+/// A ROM where every byte holds the number of its bank, so tests can tell which bank is mapped.
+#[cfg(test)]
+pub fn banked_test_rom(
+    mapper: u16,
+    prg_banks: usize,
+    prg_bank_size: usize,
+    chr_banks: usize,
+    chr_bank_size: usize,
+) -> Rom {
+    let mut rom = Rom::from_test_code(vec![]);
+    rom.prg_rom = (0..prg_banks * prg_bank_size)
+        .map(|i| (i / prg_bank_size) as u8)
+        .collect();
+    rom.chr_rom = (0..chr_banks * chr_bank_size)
+        .map(|i| (i / chr_bank_size) as u8)
+        .collect();
+    rom.mapper = mapper;
+    rom
+}
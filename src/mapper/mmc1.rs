@@ -0,0 +1,345 @@
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::mapper::{self, ChrMemory, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+// Writing a 1 into bit 4 marks the shift register as full after 5 writes
+const SHIFT_RESET: u8 = 0b1_0000;
+
+/// Mapper 1 (MMC1) https://www.nesdev.org/wiki/MMC1
+///
+/// Registers are loaded one bit at a time through a serial shift register,
+/// the fifth write to $8000-$FFFF commits the value to the register picked by
+/// bits 13-14 of its address:
+///
+///  $8000-$9FFF  Control   CPPMM (CHR mode, PRG mode, mirroring)
+///  $A000-$BFFF  CHR bank 0
+///  $C000-$DFFF  CHR bank 1
+///  $E000-$FFFF  PRG bank  RPPPP (PRG-RAM disable, PRG bank)
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    pub prg_ram: Vec<u8>,
+
+    shift_register: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc1 {
+            prg_ram: vec![0; mapper::prg_ram_size(&rom)],
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            shift_register: SHIFT_RESET,
+            // Power on in PRG mode 3, last bank fixed at $C000
+            control: 0b0_1100,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = data,
+            0xA000..=0xBFFF => self.chr_bank_0 = data,
+            0xC000..=0xDFFF => self.chr_bank_1 = data,
+            0xE000..=0xFFFF => self.prg_bank = data,
+            _ => unreachable!(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b1_0000 == 0
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+
+    // 512KiB boards (SUROM) use bit 4 of the CHR bank to select the 256KiB PRG half
+    fn prg_outer_bank(&self) -> usize {
+        if self.prg_rom.len() > 0x40000 {
+            (self.chr_bank_0 & 0b1_0000) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Maps $8000-$FFFF to a 16KiB bank number.
+    fn prg_bank_for(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let outer = self.prg_outer_bank();
+        let last = (self.prg_bank_count() - 1) & 0b1111;
+        let upper_half = addr >= 0xC000;
+
+        let bank = match (self.control >> 2) & 0b11 {
+            // 32KiB mode ignores the low bit of the bank number
+            0 | 1 => (bank & !1) | upper_half as usize,
+            // First bank fixed at $8000, switch $C000
+            2 => {
+                if upper_half {
+                    bank
+                } else {
+                    0
+                }
+            }
+            // Last bank fixed at $C000, switch $8000
+            3 => {
+                if upper_half {
+                    last
+                } else {
+                    bank
+                }
+            }
+            _ => unreachable!(),
+        };
+        (outer | bank) % self.prg_bank_count()
+    }
+
+    /// Maps $0000-$1FFF to a 4KiB bank number.
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        let upper_half = addr >= 0x1000;
+        if self.control & 0b1_0000 == 0 {
+            // 8KiB mode ignores the low bit of the bank number
+            (self.chr_bank_0 as usize & !1) | upper_half as usize
+        } else if upper_half {
+            self.chr_bank_1 as usize
+        } else {
+            self.chr_bank_0 as usize
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_bank_for(addr) * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.prg_ram_enabled() && !self.prg_ram.is_empty() {
+                    self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+                } else {
+                    0
+                }
+            }
+            0x8000..=0xFFFF => {
+                let offset = self.prg_bank_for(addr) * PRG_BANK_SIZE;
+                self.prg_rom[offset + (addr as usize % PRG_BANK_SIZE)]
+            }
+            _ => {
+                println!("Ignoring mem access at {}", addr);
+                0
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.prg_ram_enabled() && !self.prg_ram.is_empty() {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[(addr - 0x6000) as usize % len] = data;
+                }
+            }
+            0x8000..=0xFFFF => {
+                if data & 0b1000_0000 != 0 {
+                    // Reset the shift register and lock PRG mode 3
+                    self.shift_register = SHIFT_RESET;
+                    self.control |= 0b0_1100;
+                    return;
+                }
+
+                let full = self.shift_register & 1 != 0;
+                self.shift_register = (self.shift_register >> 1) | ((data & 1) << 4);
+                if full {
+                    self.write_register(addr, self.shift_register);
+                    self.shift_register = SHIFT_RESET;
+                }
+            }
+            _ => println!("Ignoring mem write-access at {}", addr),
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::VERTICAL,
+            3 => Mirroring::HORIZONTAL,
+            _ => unreachable!(),
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Savestate for Mmc1 {
+    fn save(&self, w: &mut StateWriter) {
+        self.chr.save(w);
+        w.bytes(&self.prg_ram);
+        w.u8(self.shift_register);
+        w.u8(self.control);
+        w.u8(self.chr_bank_0);
+        w.u8(self.chr_bank_1);
+        w.u8(self.prg_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.chr.load(r)?;
+        r.bytes(&mut self.prg_ram)?;
+        self.shift_register = r.u8()?;
+        self.control = r.u8()?;
+        self.chr_bank_0 = r.u8()?;
+        self.chr_bank_1 = r.u8()?;
+        self.prg_bank = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::mapper::mapper::banked_test_rom;
+
+    fn test_rom(prg_banks: usize, chr_banks: usize) -> Rom {
+        banked_test_rom(1, prg_banks, PRG_BANK_SIZE, chr_banks, CHR_BANK_SIZE)
+    }
+
+    fn serial_write(mapper: &mut Mmc1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mapper.write_prg(addr, (value >> i) & 1);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mapper = Mmc1::new(test_rom(8, 2));
+
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+    }
+
+    #[test]
+    fn test_shift_register_commits_on_fifth_write() {
+        let mut mapper = Mmc1::new(test_rom(8, 2));
+
+        for i in 0..4 {
+            mapper.write_prg(0xE000, (3 >> i) & 1);
+            assert_eq!(mapper.read_prg(0x8000), 0);
+        }
+        mapper.write_prg(0xE000, 0);
+
+        assert_eq!(mapper.read_prg(0x8000), 3);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+    }
+
+    #[test]
+    fn test_reset_bit_clears_shift_register() {
+        let mut mapper = Mmc1::new(test_rom(8, 2));
+
+        mapper.write_prg(0xE000, 1);
+        mapper.write_prg(0xE000, 1);
+        mapper.write_prg(0xE000, 0x80);
+        serial_write(&mut mapper, 0xE000, 2);
+
+        assert_eq!(mapper.read_prg(0x8000), 2);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = Mmc1::new(test_rom(8, 2));
+
+        // Mode 2: first bank fixed, switch $C000
+        serial_write(&mut mapper, 0x8000, 0b0_1000);
+        serial_write(&mut mapper, 0xE000, 5);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        assert_eq!(mapper.read_prg(0xC000), 5);
+
+        // Mode 0: 32KiB, low bit ignored
+        serial_write(&mut mapper, 0x8000, 0b0_0000);
+        assert_eq!(mapper.read_prg(0x8000), 4);
+        assert_eq!(mapper.read_prg(0xC000), 5);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mapper = Mmc1::new(test_rom(2, 4));
+
+        // 8KiB mode
+        serial_write(&mut mapper, 0xA000, 3);
+        assert_eq!(mapper.read_chr(0x0000), 2);
+        assert_eq!(mapper.read_chr(0x1000), 3);
+
+        // 4KiB mode
+        serial_write(&mut mapper, 0x8000, 0b1_1100);
+        serial_write(&mut mapper, 0xA000, 1);
+        serial_write(&mut mapper, 0xC000, 3);
+        assert_eq!(mapper.read_chr(0x0000), 1);
+        assert_eq!(mapper.read_chr(0x1000), 3);
+    }
+
+    #[test]
+    fn test_mirroring_control() {
+        let mut mapper = Mmc1::new(test_rom(2, 2));
+
+        serial_write(&mut mapper, 0x8000, 0b0_1100);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        serial_write(&mut mapper, 0x8000, 0b0_1110);
+        assert_eq!(mapper.mirroring(), Mirroring::VERTICAL);
+        serial_write(&mut mapper, 0x8000, 0b0_1111);
+        assert_eq!(mapper.mirroring(), Mirroring::HORIZONTAL);
+    }
+
+    #[test]
+    fn test_prg_ram_can_be_disabled() {
+        let mut mapper = Mmc1::new(test_rom(2, 2));
+
+        mapper.write_prg(0x6000, 0x42);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+
+        serial_write(&mut mapper, 0xE000, 0b1_0000);
+        mapper.write_prg(0x6001, 0x43);
+        assert_eq!(mapper.read_prg(0x6000), 0);
+
+        serial_write(&mut mapper, 0xE000, 0);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+        assert_eq!(mapper.read_prg(0x6001), 0);
+    }
+
+    #[test]
+    fn test_chr_ram_when_no_chr_rom() {
+        let mut mapper = Mmc1::new(test_rom(2, 0));
+
+        mapper.write_chr(0x0010, 0x42);
+
+        assert_eq!(mapper.read_chr(0x0010), 0x42);
+    }
+}
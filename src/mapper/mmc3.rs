@@ -0,0 +1,380 @@
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::mapper::{self, ChrMemory, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mapper 4 (MMC3) https://www.nesdev.org/wiki/MMC3
+///
+/// Registers are selected by address range and parity:
+///
+///  $8000 even  Bank select     CP...RRR (CHR A12 inversion, PRG mode, target R0-R7)
+///  $8001 odd   Bank data
+///  $A000 even  Mirroring       .......M (0: vertical; 1: horizontal)
+///  $A001 odd   PRG-RAM protect EW...... (enable, write protect)
+///  $C000 even  IRQ latch
+///  $C001 odd   IRQ reload
+///  $E000 even  IRQ disable and acknowledge
+///  $E001 odd   IRQ enable
+///
+/// The scanline counter is clocked by rising edges of PPU A12, which happen once
+/// per scanline when background and sprites use different pattern tables.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    pub prg_ram: Vec<u8>,
+    four_screen: bool,
+
+    bank_select: u8,
+    registers: [u8; 8],
+    horizontal_mirroring: bool,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    last_a12: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc3 {
+            prg_ram: vec![0; mapper::prg_ram_size(&rom)],
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal_mirroring: rom.screen_mirroring == Mirroring::HORIZONTAL,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            last_a12: false,
+        }
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+
+    /// Maps $8000-$FFFF to an 8KiB bank number.
+    fn prg_bank_for(&self, addr: u16) -> usize {
+        // A single 8KiB bank fills every slot
+        let second_last = self.prg_bank_count().saturating_sub(2);
+        let last = self.prg_bank_count() - 1;
+        let r6 = (self.registers[6] & 0b0011_1111) as usize;
+        let r7 = (self.registers[7] & 0b0011_1111) as usize;
+        let swapped = self.bank_select & 0b0100_0000 != 0;
+
+        let bank = match (addr, swapped) {
+            (0x8000..=0x9FFF, false) => r6,
+            (0x8000..=0x9FFF, true) => second_last,
+            (0xA000..=0xBFFF, _) => r7,
+            (0xC000..=0xDFFF, false) => second_last,
+            (0xC000..=0xDFFF, true) => r6,
+            _ => last,
+        };
+        bank % self.prg_bank_count()
+    }
+
+    /// Maps $0000-$1FFF to a 1KiB bank number.
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        let inverted = self.bank_select & 0b1000_0000 != 0;
+        // With A12 inversion the 2KiB banks move to $1000-$1FFF
+        let addr = if inverted { addr ^ 0x1000 } else { addr };
+        let slot = (addr as usize / CHR_BANK_SIZE) & 0b111;
+
+        match slot {
+            0 => (self.registers[0] & !1) as usize,
+            1 => (self.registers[0] | 1) as usize,
+            2 => (self.registers[1] & !1) as usize,
+            3 => (self.registers[1] | 1) as usize,
+            _ => self.registers[slot - 2] as usize,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_bank_for(addr) * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match (addr, even) {
+            (0x8000..=0x9FFF, true) => self.bank_select = data,
+            (0x8000..=0x9FFF, false) => {
+                self.registers[(self.bank_select & 0b111) as usize] = data;
+            }
+            (0xA000..=0xBFFF, true) => self.horizontal_mirroring = data & 1 != 0,
+            (0xA000..=0xBFFF, false) => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_write_protect = data & 0b0100_0000 != 0;
+            }
+            (0xC000..=0xDFFF, true) => self.irq_latch = data,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000..=0xFFFF, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000..=0xFFFF, false) => self.irq_enabled = true,
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.prg_ram_enabled && !self.prg_ram.is_empty() {
+                    self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+                } else {
+                    0
+                }
+            }
+            0x8000..=0xFFFF => {
+                let offset = self.prg_bank_for(addr) * PRG_BANK_SIZE;
+                self.prg_rom[offset + (addr as usize % PRG_BANK_SIZE)]
+            }
+            _ => {
+                println!("Ignoring mem access at {}", addr);
+                0
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => {
+                let writable = self.prg_ram_enabled && !self.prg_ram_write_protect;
+                if writable && !self.prg_ram.is_empty() {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[(addr - 0x6000) as usize % len] = data;
+                }
+            }
+            0x8000..=0xFFFF => self.write_register(addr, data),
+            _ => println!("Ignoring mem write-access at {}", addr),
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else if self.horizontal_mirroring {
+            Mirroring::HORIZONTAL
+        } else {
+            Mirroring::VERTICAL
+        }
+    }
+
+    fn ppu_bus_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.last_a12 {
+            self.clock_irq_counter();
+        }
+        self.last_a12 = a12;
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Savestate for Mmc3 {
+    fn save(&self, w: &mut StateWriter) {
+        self.chr.save(w);
+        w.bytes(&self.prg_ram);
+        w.u8(self.bank_select);
+        w.bytes(&self.registers);
+        w.bool(self.horizontal_mirroring);
+        w.bool(self.prg_ram_enabled);
+        w.bool(self.prg_ram_write_protect);
+        w.u8(self.irq_latch);
+        w.u8(self.irq_counter);
+        w.bool(self.irq_reload);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_pending);
+        w.bool(self.last_a12);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.chr.load(r)?;
+        r.bytes(&mut self.prg_ram)?;
+        self.bank_select = r.u8()?;
+        r.bytes(&mut self.registers)?;
+        self.horizontal_mirroring = r.bool()?;
+        self.prg_ram_enabled = r.bool()?;
+        self.prg_ram_write_protect = r.bool()?;
+        self.irq_latch = r.u8()?;
+        self.irq_counter = r.u8()?;
+        self.irq_reload = r.bool()?;
+        self.irq_enabled = r.bool()?;
+        self.irq_pending = r.bool()?;
+        self.last_a12 = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::mapper::banked_test_rom;
+
+    fn test_mapper() -> Mmc3 {
+        Mmc3::new(banked_test_rom(4, 8, PRG_BANK_SIZE, 16, CHR_BANK_SIZE))
+    }
+
+    fn set_register(mapper: &mut Mmc3, register: u8, value: u8) {
+        let mode = mapper.bank_select & 0b1100_0000;
+        mapper.write_prg(0x8000, mode | register);
+        mapper.write_prg(0x8001, value);
+    }
+
+    fn scanline(mapper: &mut Mmc3) {
+        mapper.ppu_bus_address(0x0000);
+        mapper.ppu_bus_address(0x1000);
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_mapper();
+        set_register(&mut mapper, 6, 3);
+        set_register(&mut mapper, 7, 4);
+
+        assert_eq!(mapper.read_prg(0x8000), 3);
+        assert_eq!(mapper.read_prg(0xA000), 4);
+        assert_eq!(mapper.read_prg(0xC000), 6);
+        assert_eq!(mapper.read_prg(0xE000), 7);
+
+        // PRG mode 1 swaps $8000 and $C000
+        mapper.write_prg(0x8000, 0b0100_0000);
+        assert_eq!(mapper.read_prg(0x8000), 6);
+        assert_eq!(mapper.read_prg(0xC000), 3);
+        assert_eq!(mapper.read_prg(0xE000), 7);
+    }
+
+    #[test]
+    fn test_single_prg_bank() {
+        let mapper = Mmc3::new(banked_test_rom(4, 1, PRG_BANK_SIZE, 16, CHR_BANK_SIZE));
+
+        for addr in [0x8000, 0xA000, 0xC000, 0xE000] {
+            assert_eq!(mapper.read_prg(addr), 0);
+        }
+    }
+
+    #[test]
+    fn test_chr_banks_and_inversion() {
+        let mut mapper = test_mapper();
+        set_register(&mut mapper, 0, 9); // low bit ignored
+        set_register(&mut mapper, 2, 12);
+
+        assert_eq!(mapper.read_chr(0x0000), 8);
+        assert_eq!(mapper.read_chr(0x0400), 9);
+        assert_eq!(mapper.read_chr(0x1000), 12);
+
+        mapper.write_prg(0x8000, 0b1000_0000);
+        assert_eq!(mapper.read_chr(0x1000), 8);
+        assert_eq!(mapper.read_chr(0x1400), 9);
+        assert_eq!(mapper.read_chr(0x0000), 12);
+    }
+
+    #[test]
+    fn test_mirroring_control() {
+        let mut mapper = test_mapper();
+
+        mapper.write_prg(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::VERTICAL);
+        mapper.write_prg(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::HORIZONTAL);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mapper = test_mapper();
+        mapper.write_prg(0xA001, 0b1000_0000);
+        mapper.write_prg(0x6000, 0x42);
+
+        mapper.write_prg(0xA001, 0b1100_0000);
+        mapper.write_prg(0x6000, 0x43);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+
+        mapper.write_prg(0xA001, 0);
+        assert_eq!(mapper.read_prg(0x6000), 0);
+    }
+
+    #[test]
+    fn test_irq_after_latch_scanlines() {
+        let mut mapper = test_mapper();
+        mapper.write_prg(0xC000, 3); // latch
+        mapper.write_prg(0xC001, 0); // reload
+        mapper.write_prg(0xE001, 0); // enable
+
+        // First edge reloads the counter with 3
+        for _ in 0..3 {
+            scanline(&mut mapper);
+            assert!(!mapper.irq_pending());
+        }
+        scanline(&mut mapper);
+        assert!(mapper.irq_pending());
+
+        mapper.write_prg(0xE000, 0); // acknowledge
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_a12_staying_high_does_not_clock() {
+        let mut mapper = test_mapper();
+        mapper.write_prg(0xC000, 1);
+        mapper.write_prg(0xE001, 0);
+
+        mapper.ppu_bus_address(0x1000);
+        mapper.ppu_bus_address(0x1010);
+        mapper.ppu_bus_address(0x1ff0);
+
+        assert_eq!(mapper.irq_counter, 1);
+        assert!(!mapper.irq_pending());
+    }
+}
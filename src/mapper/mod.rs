@@ -0,0 +1,8 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod gxrom;
+pub mod mapper;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
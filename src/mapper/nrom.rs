@@ -0,0 +1,123 @@
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::mapper::{self, ChrMemory, Mapper};
+
+/// Mapper 0, no bank switching: 16KiB or 32KiB of PRG-ROM and 8KiB of CHR.
+/// Some boards (Family BASIC, most test ROMs) add PRG-RAM at $6000-$7FFF.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    fault: Option<EmuError>,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            prg_ram: vec![0; mapper::prg_ram_size(&rom)],
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+            fault: None,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            // Smaller RAM chips are mirrored through the whole window
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => {
+                // 16KiB carts are mirrored into $C000-$FFFF
+                let addr = (addr - 0x8000) as usize % self.prg_rom.len();
+                self.prg_rom[addr]
+            }
+            _ => {
+                println!("Ignoring mem access at {}", addr);
+                0
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x8000..=0xFFFF => self.fault = Some(EmuError::UnhandledRomWrite { addr, data }),
+            _ => println!("Ignoring mem write-access at {}", addr),
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn take_fault(&mut self) -> Option<EmuError> {
+        self.fault.take()
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Savestate for Nrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.chr.save(w);
+        w.bytes(&self.prg_ram);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.chr.load(r)?;
+        r.bytes(&mut self.prg_ram)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prg_ram() {
+        let mut rom = Rom::from_test_code(vec![]);
+        rom.prg_ram_size = 0x800;
+        let mut mapper = Nrom::new(rom);
+
+        mapper.write_prg(0x6004, 0x42);
+        assert_eq!(mapper.read_prg(0x6004), 0x42);
+        assert_eq!(mapper.read_prg(0x6804), 0x42);
+    }
+
+    #[test]
+    fn test_without_prg_ram() {
+        let mut rom = Rom::from_test_code(vec![]);
+        rom.prg_ram_size = 0;
+        let mut mapper = Nrom::new(rom);
+
+        mapper.write_prg(0x6000, 0x42);
+        assert_eq!(mapper.read_prg(0x6000), 0);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    error::EmuError,
+    rom::{Mirroring, Rom},
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::mapper::{ChrMemory, Mapper, bus_conflict};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 2 (UxROM) https://www.nesdev.org/wiki/UxROM
+///
+/// Writes to $8000-$FFFF select the 16KiB bank at $8000-$BFFF,
+/// the last bank is fixed at $C000-$FFFF.
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    bank: usize,
+    pub bus_conflicts: bool,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+            bank: 0,
+            // UNROM and UOROM both have them, NES 2.0 submapper 1 says the board doesn't
+            bus_conflicts: rom.submapper != 1,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+}
+
+impl Mapper for Uxrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xBFFF => {
+                let offset = (self.bank % self.bank_count()) * PRG_BANK_SIZE;
+                self.prg_rom[offset + (addr as usize - 0x8000)]
+            }
+            0xC000..=0xFFFF => {
+                let offset = (self.bank_count() - 1) * PRG_BANK_SIZE;
+                self.prg_rom[offset + (addr as usize - 0xC000)]
+            }
+            _ => {
+                println!("Ignoring mem access at {}", addr);
+                0
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => {
+                let data = if self.bus_conflicts {
+                    bus_conflict(self, addr, data)
+                } else {
+                    data
+                };
+                self.bank = data as usize;
+            }
+            _ => println!("Ignoring mem write-access at {}", addr),
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Savestate for Uxrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.chr.save(w);
+        w.usize(self.bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.chr.load(r)?;
+        self.bank = r.usize()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::mapper::banked_test_rom;
+
+    #[test]
+    fn test_switches_low_bank_and_fixes_last() {
+        let mut mapper = Uxrom::new(banked_test_rom(2, 8, PRG_BANK_SIZE, 0, 0));
+        mapper.bus_conflicts = false;
+
+        mapper.write_prg(0x8000, 3);
+
+        assert_eq!(mapper.read_prg(0x8000), 3);
+        assert_eq!(mapper.read_prg(0xBFFF), 3);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+    }
+
+    #[test]
+    fn test_bus_conflicts_with_the_rom_byte() {
+        let mut mapper = Uxrom::new(banked_test_rom(2, 8, PRG_BANK_SIZE, 0, 0));
+
+        // The ROM byte at $8000 is 0, the one at $C000 is 7
+        mapper.write_prg(0x8000, 0xff);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+
+        mapper.write_prg(0xC000, 0xff);
+        assert_eq!(mapper.read_prg(0x8000), 7);
+    }
+
+    #[test]
+    fn test_has_chr_ram() {
+        let mut mapper = Uxrom::new(banked_test_rom(2, 8, PRG_BANK_SIZE, 0, 0));
+
+        mapper.write_chr(0x1234, 0x42);
+
+        assert_eq!(mapper.read_chr(0x1234), 0x42);
+    }
+}
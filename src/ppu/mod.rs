@@ -0,0 +1,6 @@
+pub mod frame;
+pub mod palette;
+pub mod ppu;
+pub mod registers;
+pub mod render;
+pub mod sprites;
@@ -0,0 +1,691 @@
+use crate::{
+    error::EmuError,
+    mapper::mapper::SharedMapper,
+    rom::Mirroring,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::frame::Frame;
+use super::registers::{
+    control::ControlRegister, mask::MaskRegister, status::StatusRegister, vram_addr::VramAddr,
+};
+use super::render::BackgroundShifters;
+use super::sprites::LineSprite;
+
+const DOTS_PER_SCANLINE: usize = 341;
+const VISIBLE_SCANLINES: u16 = 240;
+const VBLANK_SCANLINE: u16 = 241;
+pub(super) const PRE_RENDER_SCANLINE: u16 = 261;
+const SCANLINES_PER_FRAME: u16 = 262;
+
+pub struct NesPPU {
+    // CHR-ROM/RAM and mirroring come from the cartridge
+    pub cartridge: SharedMapper,
+    pub palette_table: [u8; 32],
+    pub vram: [u8; 2048],
+    pub oam_addr: u8,
+    pub oam_data: [u8; 256],
+
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+    // Current VRAM address, also the scroll position while rendering
+    pub v: VramAddr,
+    // Scroll position and PPUADDR as written, copied into v during rendering
+    pub t: VramAddr,
+    pub fine_x: u8,
+
+    pub scanline: u16,
+    // Dot within the current scanline
+    pub cycles: usize,
+    pub nmi_interrupt: bool,
+    pub frame: Frame,
+    pub(super) background: BackgroundShifters,
+    // Sprites found for the scanline being drawn, in OAM order
+    pub(super) line_sprites: Vec<LineSprite>,
+    /// Draw every sprite on a scanline instead of the first 8, hides flicker
+    pub no_sprite_limit: bool,
+
+    // Shared first/second write toggle of PPUSCROLL and PPUADDR
+    write_latch: bool,
+    internal_data_buf: u8,
+    // Last value written to any register, returned when reading write-only ones
+    open_bus: u8,
+}
+
+impl NesPPU {
+    pub fn new(cartridge: SharedMapper) -> Self {
+        NesPPU {
+            cartridge,
+            palette_table: [0; 32],
+            vram: [0; 2048],
+            oam_addr: 0,
+            oam_data: [0; 256],
+            ctrl: ControlRegister::new(),
+            mask: MaskRegister::new(),
+            status: StatusRegister::new(),
+            v: VramAddr::new(),
+            t: VramAddr::new(),
+            fine_x: 0,
+            scanline: 0,
+            cycles: 0,
+            nmi_interrupt: false,
+            frame: Frame::new(),
+            background: BackgroundShifters::default(),
+            line_sprites: Vec::new(),
+            no_sprite_limit: false,
+            write_latch: false,
+            internal_data_buf: 0,
+            open_bus: 0,
+        }
+    }
+
+    /// Advances the PPU by the given number of dots, returns true once a frame is complete.
+    pub fn tick(&mut self, cycles: usize) -> bool {
+        let mut frame_complete = false;
+        for _ in 0..cycles {
+            self.cycles += 1;
+            if self.is_rendering() {
+                self.emit_pattern_fetch();
+                self.fetch_background();
+            }
+            if self.scanline < VISIBLE_SCANLINES {
+                match self.cycles {
+                    1..=256 => self.render_pixel(self.cycles - 1),
+                    // Sprites for the next scanline are picked while this one finishes
+                    257 if self.mask.rendering_enabled() => self.evaluate_sprites(),
+                    _ => {}
+                }
+            }
+
+            if self.scanline == PRE_RENDER_SCANLINE && self.cycles == 1 {
+                self.status.set_vblank_status(false);
+                self.status.set_sprite_zero_hit(false);
+                self.status.set_sprite_overflow(false);
+            }
+
+            if self.cycles < DOTS_PER_SCANLINE {
+                continue;
+            }
+
+            self.cycles = 0;
+            self.scanline += 1;
+
+            if self.scanline == VBLANK_SCANLINE {
+                self.status.set_vblank_status(true);
+                if self.ctrl.generate_vblank_nmi() {
+                    self.nmi_interrupt = true;
+                }
+            }
+
+            if self.scanline == SCANLINES_PER_FRAME {
+                self.scanline = 0;
+                // Nothing is evaluated on the pre-render line, so scanline 0 has no sprites
+                self.line_sprites.clear();
+                self.nmi_interrupt = false;
+                frame_complete = true;
+            }
+        }
+        frame_complete
+    }
+
+    // Fetching and drawing only happen on these lines, and not at all with rendering off
+    fn is_rendering(&self) -> bool {
+        self.mask.rendering_enabled()
+            && (self.scanline < VISIBLE_SCANLINES || self.scanline == PRE_RENDER_SCANLINE)
+    }
+
+    /// Puts the pattern table address of the current fetch on the PPU bus, so the
+    /// cartridge can follow A12. Only the pattern fetch of every 8 dot group is reported.
+    fn emit_pattern_fetch(&mut self) {
+        if self.cycles % 8 != 5 {
+            return;
+        }
+
+        let addr = match self.cycles {
+            // Background tiles, including the prefetch for the next scanline
+            1..=256 | 321..=336 => self.ctrl.background_pattern_addr(),
+            // Sprites, unused slots fetch tile $FF which lives at $1000 in 8x16 mode
+            257..=320 if self.ctrl.sprite_size() == 16 => 0x1000,
+            257..=320 => self.ctrl.sprite_pattern_addr(),
+            _ => return,
+        };
+        self.cartridge.borrow_mut().ppu_bus_address(addr);
+    }
+
+    /// Reads one of the eight registers, `addr` is already mirrored down to $2000-$2007.
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x2002 => self.read_status(),
+            0x2004 => self.read_oam_data(),
+            0x2007 => self.read_data(),
+            // PPUCTRL, PPUMASK, OAMADDR, PPUSCROLL and PPUADDR are write-only
+            _ => self.open_bus,
+        }
+    }
+
+    /// Like `read_register` but leaves the PPU untouched, used by debugging tools.
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x2002 => self.status.bits() | (self.open_bus & 0b0001_1111),
+            0x2004 => self.read_oam_data(),
+            0x2007 => self.internal_data_buf,
+            _ => self.open_bus,
+        }
+    }
+
+    /// Writes one of the eight registers, `addr` is already mirrored down to $2000-$2007.
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr {
+            0x2000 => self.write_to_ctrl(data),
+            0x2001 => self.write_to_mask(data),
+            0x2002 => { /* PPUSTATUS is read-only */ }
+            0x2003 => self.write_to_oam_addr(data),
+            0x2004 => self.write_to_oam_data(data),
+            0x2005 => self.write_to_scroll(data),
+            0x2006 => self.write_to_ppu_addr(data),
+            0x2007 => self.write_to_data(data),
+            _ => unreachable!("PPU register {:04x} out of range", addr),
+        }
+    }
+
+    /// Returns and clears the NMI raised at the start of vblank.
+    pub fn poll_nmi_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_interrupt)
+    }
+
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        let before_nmi_status = self.ctrl.generate_vblank_nmi();
+        self.ctrl.update(value);
+        self.t.set_nametable(value);
+        // Enabling NMI while already in vblank fires it immediately
+        if !before_nmi_status && self.ctrl.generate_vblank_nmi() && self.status.is_in_vblank() {
+            self.nmi_interrupt = true;
+        }
+    }
+
+    pub fn write_to_mask(&mut self, value: u8) {
+        self.mask.update(value);
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let data = self.status.bits() | (self.open_bus & 0b0001_1111);
+        self.status.set_vblank_status(false);
+        self.write_latch = false;
+        data
+    }
+
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.oam_addr = value;
+    }
+
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    pub fn read_oam_data(&self) -> u8 {
+        self.oam_data[self.oam_addr as usize]
+    }
+
+    /// The first write sets the X scroll, the second one the Y scroll.
+    pub fn write_to_scroll(&mut self, value: u8) {
+        if !self.write_latch {
+            self.t.set_scroll_x(value);
+            self.fine_x = value & 0b111;
+        } else {
+            self.t.set_scroll_y(value);
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    /// The first write sets the high byte, the second one the low byte and copies t into v.
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        if !self.write_latch {
+            self.t.set_high_byte(value);
+        } else {
+            self.t.set_low_byte(value);
+            self.v = self.t;
+            // The complete address is now on the PPU bus
+            self.cartridge
+                .borrow_mut()
+                .ppu_bus_address(self.vram_addr());
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    // PPUDATA sees the low 14 bits of v
+    fn vram_addr(&self) -> u16 {
+        self.v.get() & 0x3fff
+    }
+
+    fn increment_vram_addr(&mut self) {
+        if self.is_rendering() {
+            // While rendering, the access bumps both scroll counters instead
+            self.v.increment_x();
+            self.v.increment_y();
+        } else {
+            self.v.increment(self.ctrl.vram_addr_increment());
+        }
+    }
+
+    pub fn write_to_data(&mut self, value: u8) {
+        let addr = self.vram_addr();
+        self.cartridge.borrow_mut().ppu_bus_address(addr);
+        match addr {
+            0..=0x1fff => self.cartridge.borrow_mut().write_chr(addr, value),
+            0x2000..=0x3eff => {
+                self.vram[self.mirror_vram_addr(addr) as usize] = value;
+            }
+            0x3f00..=0x3fff => {
+                self.palette_table[Self::mirror_palette_addr(addr)] = value;
+            }
+            _ => unreachable!("unexpected access to mirrored space {:04x}", addr),
+        }
+        self.increment_vram_addr();
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let addr = self.vram_addr();
+        self.cartridge.borrow_mut().ppu_bus_address(addr);
+        self.increment_vram_addr();
+
+        match addr {
+            0..=0x1fff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.cartridge.borrow().read_chr(addr);
+                result
+            }
+            0x2000..=0x3eff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                result
+            }
+            0x3f00..=0x3fff => {
+                // Palette reads are not buffered, but the buffer is still filled
+                // with the nametable byte "underneath" the palette
+                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr - 0x1000) as usize];
+                self.palette_table[Self::mirror_palette_addr(addr)]
+            }
+            _ => unreachable!("unexpected access to mirrored space {:04x}", addr),
+        }
+    }
+
+    // Horizontal:
+    //   [ A ] [ a ]
+    //   [ B ] [ b ]
+
+    // Vertical:
+    //   [ A ] [ B ]
+    //   [ a ] [ b ]
+    pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
+        let mirrored_vram = addr & 0b10_1111_1111_1111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
+        let vram_index = mirrored_vram - 0x2000; // to vram vector
+        let name_table = vram_index / 0x400; // to the name table index
+        match (self.cartridge.borrow().mirroring(), name_table) {
+            (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) => vram_index - 0x800,
+            (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
+            (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
+            (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
+            // Four-screen boards carry their own extra 2KiB; without it wrap into ours
+            (Mirroring::FourScreen, _) => vram_index & 0x07ff,
+            (Mirroring::SingleScreenLower, _) => vram_index & 0x03ff,
+            (Mirroring::SingleScreenUpper, _) => 0x0400 | (vram_index & 0x03ff),
+            _ => vram_index,
+        }
+    }
+
+    // $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
+    fn mirror_palette_addr(addr: u16) -> usize {
+        let index = (addr & 0x1f) as usize;
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1c => index - 0x10,
+            _ => index,
+        }
+    }
+}
+
+// The frame buffer is redrawn every frame, so it is left out
+impl Savestate for NesPPU {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.palette_table);
+        w.bytes(&self.vram);
+        w.u8(self.oam_addr);
+        w.bytes(&self.oam_data);
+        w.u8(self.ctrl.bits());
+        w.u8(self.mask.bits());
+        w.u8(self.status.bits());
+        self.v.save(w);
+        self.t.save(w);
+        w.u8(self.fine_x);
+        w.u16(self.scanline);
+        w.usize(self.cycles);
+        w.bool(self.nmi_interrupt);
+        self.background.save(w);
+        w.u8(self.line_sprites.len() as u8);
+        for sprite in &self.line_sprites {
+            sprite.save(w);
+        }
+        w.bool(self.write_latch);
+        w.u8(self.internal_data_buf);
+        w.u8(self.open_bus);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        r.bytes(&mut self.palette_table)?;
+        r.bytes(&mut self.vram)?;
+        self.oam_addr = r.u8()?;
+        r.bytes(&mut self.oam_data)?;
+        self.ctrl = ControlRegister::from_bits_retain(r.u8()?);
+        self.mask = MaskRegister::from_bits_retain(r.u8()?);
+        self.status = StatusRegister::from_bits_retain(r.u8()?);
+        self.v.load(r)?;
+        self.t.load(r)?;
+        self.fine_x = r.u8()? & 0b111;
+        self.scanline = r.u16()?;
+        if self.scanline >= SCANLINES_PER_FRAME {
+            return Err(StateReader::invalid("scanline", self.scanline));
+        }
+        self.cycles = r.usize()?;
+        self.nmi_interrupt = r.bool()?;
+        self.background.load(r)?;
+        let sprite_count = r.u8()?;
+        self.line_sprites.clear();
+        for _ in 0..sprite_count {
+            let mut sprite = LineSprite::default();
+            sprite.load(r)?;
+            self.line_sprites.push(sprite);
+        }
+        self.write_latch = r.bool()?;
+        self.internal_data_buf = r.u8()?;
+        self.open_bus = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl NesPPU {
+    pub fn new_empty_rom() -> Self {
+        NesPPU::new_with_mirroring(Mirroring::HORIZONTAL)
+    }
+
+    pub fn new_with_mirroring(mirroring: Mirroring) -> Self {
+        let mut rom = crate::rom::Rom::from_test_code(vec![]);
+        rom.screen_mirroring = mirroring;
+        NesPPU::new(crate::mapper::mapper::from_rom(rom).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ppu_vram_writes() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+
+        assert_eq!(ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.v.get(), 0x2306);
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_cross_page() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x0200] = 0x77;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0xff);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66);
+        assert_eq!(ppu.read_data(), 0x77);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_step_32() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b100);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x01ff + 32] = 0x77;
+        ppu.vram[0x01ff + 64] = 0x88;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0xff);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66);
+        assert_eq!(ppu.read_data(), 0x77);
+        assert_eq!(ppu.read_data(), 0x88);
+    }
+
+    // Horizontal: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 a ]
+    //   [0x2800 B ] [0x2C00 b ]
+    #[test]
+    fn test_vram_horizontal_mirror() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x66); // write to a
+
+        ppu.write_to_ppu_addr(0x28);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x77); // write to B
+
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66); // read from A
+
+        ppu.write_to_ppu_addr(0x2C);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x77); // read from b
+    }
+
+    // Vertical: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 B ]
+    //   [0x2800 a ] [0x2C00 b ]
+    #[test]
+    fn test_vram_vertical_mirror() {
+        let mut ppu = NesPPU::new_with_mirroring(Mirroring::VERTICAL);
+
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x66); // write to A
+
+        ppu.write_to_ppu_addr(0x2C);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x77); // write to b
+
+        ppu.write_to_ppu_addr(0x28);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66); // read from a
+
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x77); // read from B
+    }
+
+    #[test]
+    fn test_read_status_resets_latch() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_ne!(ppu.read_data(), 0x66);
+
+        ppu.read_status();
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_mirroring() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x63); // 0x6305 -> 0x2305
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_read_status_resets_vblank() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.status.set_vblank_status(true);
+
+        let status = ppu.read_status();
+
+        assert_eq!(status >> 7, 1);
+        assert_eq!(ppu.status.bits() >> 7, 0);
+    }
+
+    #[test]
+    fn test_scroll_and_addr_share_latch() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_scroll(0x12);
+        ppu.write_to_ppu_addr(0x34);
+
+        assert_eq!(ppu.fine_x, 0x12 & 0b111);
+        // Second write, so the low byte
+        assert_eq!(ppu.t.get() & 0xff, 0x34);
+        assert_eq!(ppu.v, ppu.t);
+    }
+
+    #[test]
+    fn test_palette_reads_are_not_buffered() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0x2c);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x00);
+
+        assert_eq!(ppu.read_data(), 0x2c);
+    }
+
+    #[test]
+    fn test_tick_sets_and_clears_vblank() {
+        let mut ppu = NesPPU::new_empty_rom();
+
+        for _ in 0..241 {
+            assert!(!ppu.status.is_in_vblank());
+            ppu.tick(255);
+            ppu.tick(86);
+        }
+        assert_eq!(ppu.scanline, 241);
+        assert!(ppu.status.is_in_vblank());
+
+        let mut frame_complete = false;
+        for _ in 241..262 {
+            frame_complete |= ppu.tick(255);
+            frame_complete |= ppu.tick(86);
+        }
+        assert!(frame_complete);
+        assert_eq!(ppu.scanline, 0);
+        assert!(!ppu.status.is_in_vblank());
+    }
+
+    #[test]
+    fn test_nmi_raised_on_vblank_when_enabled() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b1000_0000);
+
+        for _ in 0..241 {
+            ppu.tick(255);
+            ppu.tick(86);
+        }
+
+        assert!(ppu.poll_nmi_interrupt());
+        assert!(!ppu.poll_nmi_interrupt());
+    }
+
+    #[test]
+    fn test_enabling_nmi_during_vblank_raises_it() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.status.set_vblank_status(true);
+
+        ppu.write_to_ctrl(0b1000_0000);
+
+        assert!(ppu.poll_nmi_interrupt());
+    }
+
+    #[test]
+    fn test_rendering_clocks_cartridge_through_a12() {
+        let mut rom = crate::rom::Rom::from_test_code(vec![]);
+        rom.mapper = 4;
+        let mut ppu = NesPPU::new(crate::mapper::mapper::from_rom(rom).unwrap());
+        {
+            let mut cartridge = ppu.cartridge.borrow_mut();
+            cartridge.write_prg(0xC000, 0); // IRQ on every scanline
+            cartridge.write_prg(0xE001, 0);
+        }
+        // Background from $0000, sprites from $1000
+        ppu.write_to_ctrl(0b0000_1000);
+
+        ppu.tick(255);
+        ppu.tick(86);
+        assert!(!ppu.cartridge.borrow().irq_pending());
+
+        ppu.write_to_mask(0b0001_1000);
+        ppu.tick(255);
+        ppu.tick(86);
+        assert!(ppu.cartridge.borrow().irq_pending());
+    }
+
+    #[test]
+    fn test_oam_read_write() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_to_oam_data(0x66);
+        ppu.write_to_oam_data(0x77);
+
+        ppu.write_to_oam_addr(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x66);
+
+        ppu.write_to_oam_addr(0x11);
+        assert_eq!(ppu.read_oam_data(), 0x77);
+    }
+}
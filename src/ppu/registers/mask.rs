@@ -0,0 +1,62 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// # Mask Register (PPUMASK) https://www.nesdev.org/wiki/PPU_registers#PPUMASK
+    ///
+    ///  7 6 5 4 3 2 1 0
+    ///  B G R s b M m G
+    ///  | | | | | | | +--- Greyscale (0: normal color, 1: produce a greyscale display)
+    ///  | | | | | | +----- 1: Show background in leftmost 8 pixels of screen, 0: Hide
+    ///  | | | | | +------- 1: Show sprites in leftmost 8 pixels of screen, 0: Hide
+    ///  | | | | +--------- 1: Show background
+    ///  | | | +----------- 1: Show sprites
+    ///  | | +------------- Emphasize red
+    ///  | +--------------- Emphasize green
+    ///  +----------------- Emphasize blue
+    ///
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+    pub struct MaskRegister: u8 {
+        const GREYSCALE               = 0b0000_0001;
+        const LEFTMOST_8PXL_BACKGROUND = 0b0000_0010;
+        const LEFTMOST_8PXL_SPRITE    = 0b0000_0100;
+        const SHOW_BACKGROUND         = 0b0000_1000;
+        const SHOW_SPRITES            = 0b0001_0000;
+        const EMPHASISE_RED           = 0b0010_0000;
+        const EMPHASISE_GREEN         = 0b0100_0000;
+        const EMPHASISE_BLUE          = 0b1000_0000;
+    }
+}
+
+impl MaskRegister {
+    pub fn new() -> Self {
+        MaskRegister::from_bits_truncate(0b0000_0000)
+    }
+
+    pub fn show_background(&self) -> bool {
+        self.contains(MaskRegister::SHOW_BACKGROUND)
+    }
+
+    pub fn show_sprites(&self) -> bool {
+        self.contains(MaskRegister::SHOW_SPRITES)
+    }
+
+    pub fn show_background_leftmost(&self) -> bool {
+        self.contains(MaskRegister::LEFTMOST_8PXL_BACKGROUND)
+    }
+
+    pub fn show_sprites_leftmost(&self) -> bool {
+        self.contains(MaskRegister::LEFTMOST_8PXL_SPRITE)
+    }
+
+    pub fn greyscale(&self) -> bool {
+        self.contains(MaskRegister::GREYSCALE)
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.show_background() || self.show_sprites()
+    }
+
+    pub fn update(&mut self, data: u8) {
+        *self = MaskRegister::from_bits_truncate(data);
+    }
+}
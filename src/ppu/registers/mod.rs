@@ -0,0 +1,4 @@
+pub mod control;
+pub mod mask;
+pub mod status;
+pub mod vram_addr;
@@ -0,0 +1,54 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// # Status Register (PPUSTATUS) https://www.nesdev.org/wiki/PPU_registers#PPUSTATUS
+    ///
+    ///  7 6 5 4 3 2 1 0
+    ///  V S O . . . . .
+    ///  | | | +-+-+-+-+--- Open bus: returns stale PPU bus contents
+    ///  | | +------------- Sprite overflow
+    ///  | +--------------- Sprite 0 Hit
+    ///  +----------------- Vertical blank has started (0: not in vblank; 1: in vblank)
+    ///
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+    pub struct StatusRegister: u8 {
+        const NOTUSED          = 0b0000_0001;
+        const NOTUSED2         = 0b0000_0010;
+        const NOTUSED3         = 0b0000_0100;
+        const NOTUSED4         = 0b0000_1000;
+        const NOTUSED5         = 0b0001_0000;
+        const SPRITE_OVERFLOW  = 0b0010_0000;
+        const SPRITE_ZERO_HIT  = 0b0100_0000;
+        const VBLANK_STARTED   = 0b1000_0000;
+    }
+}
+
+impl StatusRegister {
+    pub fn new() -> Self {
+        StatusRegister::from_bits_truncate(0b0000_0000)
+    }
+
+    pub fn set_vblank_status(&mut self, status: bool) {
+        self.set(StatusRegister::VBLANK_STARTED, status);
+    }
+
+    pub fn set_sprite_zero_hit(&mut self, status: bool) {
+        self.set(StatusRegister::SPRITE_ZERO_HIT, status);
+    }
+
+    pub fn set_sprite_overflow(&mut self, status: bool) {
+        self.set(StatusRegister::SPRITE_OVERFLOW, status);
+    }
+
+    pub fn is_sprite_zero_hit(&self) -> bool {
+        self.contains(StatusRegister::SPRITE_ZERO_HIT)
+    }
+
+    pub fn is_sprite_overflow(&self) -> bool {
+        self.contains(StatusRegister::SPRITE_OVERFLOW)
+    }
+
+    pub fn is_in_vblank(&self) -> bool {
+        self.contains(StatusRegister::VBLANK_STARTED)
+    }
+}
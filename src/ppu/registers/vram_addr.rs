@@ -0,0 +1,199 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+/// # Internal VRAM address (v and t) https://www.nesdev.org/wiki/PPU_scrolling
+///
+/// The PPU keeps the current address `v` and the temporary address `t` in this
+/// layout, so during rendering the same 15 bits double as the scroll position:
+///
+///  yyy NN YYYYY XXXXX
+///  ||| || ||||| +++++--- Coarse X scroll
+///  ||| || +++++--------- Coarse Y scroll
+///  ||| ++--------------- Nametable select
+///  +++------------------ Fine Y scroll
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct VramAddr(u16);
+
+const COARSE_X: u16 = 0x001f;
+const COARSE_Y: u16 = 0x03e0;
+const NAMETABLE_X: u16 = 0x0400;
+const NAMETABLE_Y: u16 = 0x0800;
+const FINE_Y: u16 = 0x7000;
+
+impl VramAddr {
+    pub fn new() -> Self {
+        VramAddr(0)
+    }
+
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.0 & COARSE_X
+    }
+
+    pub fn coarse_y(&self) -> u16 {
+        (self.0 & COARSE_Y) >> 5
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.0 & FINE_Y) >> 12
+    }
+
+    /// PPUCTRL bits 0-1
+    pub fn set_nametable(&mut self, nametable: u8) {
+        self.0 = (self.0 & !(NAMETABLE_X | NAMETABLE_Y)) | (((nametable & 0b11) as u16) << 10);
+    }
+
+    /// First PPUSCROLL write, fine X lives in its own register
+    pub fn set_scroll_x(&mut self, data: u8) {
+        self.0 = (self.0 & !COARSE_X) | (data >> 3) as u16;
+    }
+
+    /// Second PPUSCROLL write
+    pub fn set_scroll_y(&mut self, data: u8) {
+        self.0 = (self.0 & !(COARSE_Y | FINE_Y))
+            | (((data >> 3) as u16) << 5)
+            | (((data & 0b111) as u16) << 12);
+    }
+
+    /// First PPUADDR write, also clears bit 14
+    pub fn set_high_byte(&mut self, data: u8) {
+        self.0 = (self.0 & 0x00ff) | (((data & 0b0011_1111) as u16) << 8);
+    }
+
+    /// Second PPUADDR write
+    pub fn set_low_byte(&mut self, data: u8) {
+        self.0 = (self.0 & 0xff00) | data as u16;
+    }
+
+    /// PPUDATA increment outside of rendering
+    pub fn increment(&mut self, inc: u8) {
+        self.0 = self.0.wrapping_add(inc as u16) & 0x7fff;
+    }
+
+    /// Moves to the next tile, wrapping into the horizontally adjacent nametable.
+    pub fn increment_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.0 &= !COARSE_X;
+            self.0 ^= NAMETABLE_X;
+        } else {
+            self.0 += 1;
+        }
+    }
+
+    /// Moves to the next pixel row. Row 29 is the last one of a nametable, rows
+    /// 30 and 31 hold the attributes and wrap without switching nametables.
+    pub fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.0 += 1 << 12;
+            return;
+        }
+
+        self.0 &= !FINE_Y;
+        let coarse_y = match self.coarse_y() {
+            29 => {
+                self.0 ^= NAMETABLE_Y;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.0 = (self.0 & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    /// Dot 257: coarse X and the horizontal nametable come back from `t`.
+    pub fn copy_horizontal(&mut self, t: VramAddr) {
+        let mask = COARSE_X | NAMETABLE_X;
+        self.0 = (self.0 & !mask) | (t.0 & mask);
+    }
+
+    /// Pre-render dots 280-304: fine Y, coarse Y and the vertical nametable come back from `t`.
+    pub fn copy_vertical(&mut self, t: VramAddr) {
+        let mask = FINE_Y | COARSE_Y | NAMETABLE_Y;
+        self.0 = (self.0 & !mask) | (t.0 & mask);
+    }
+
+    /// Address of the nametable byte for the current tile
+    pub fn tile_addr(&self) -> u16 {
+        0x2000 | (self.0 & 0x0fff)
+    }
+
+    /// Address of the attribute byte covering the current tile
+    pub fn attribute_addr(&self) -> u16 {
+        0x23c0 | (self.0 & 0x0c00) | ((self.0 >> 4) & 0x38) | ((self.0 >> 2) & 0x07)
+    }
+}
+
+impl Savestate for VramAddr {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.0);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.0 = r.u16()? & 0x7fff;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_increment_x_wraps_into_next_nametable() {
+        let mut v = VramAddr::new();
+        v.set_scroll_x(31 << 3);
+
+        v.increment_x();
+
+        assert_eq!(v.coarse_x(), 0);
+        assert_eq!(v.tile_addr(), 0x2400);
+    }
+
+    #[test]
+    fn test_increment_y() {
+        let mut v = VramAddr::new();
+        v.set_scroll_y((29 << 3) | 7);
+
+        v.increment_y();
+        assert_eq!(v.fine_y(), 0);
+        assert_eq!(v.coarse_y(), 0);
+        assert_eq!(v.tile_addr(), 0x2800);
+
+        // Rows in the attribute table wrap without switching nametables
+        v.set_scroll_y((31 << 3) | 7);
+        v.increment_y();
+        assert_eq!(v.coarse_y(), 0);
+        assert_eq!(v.tile_addr(), 0x2800);
+    }
+
+    #[test]
+    fn test_copies_from_t() {
+        let mut t = VramAddr::new();
+        t.set_nametable(0b11);
+        t.set_scroll_x(0x18);
+        t.set_scroll_y(0x25);
+        let mut v = VramAddr::new();
+
+        v.copy_horizontal(t);
+        assert_eq!(v.get(), 0x0403);
+
+        v.copy_vertical(t);
+        assert_eq!(v, t);
+    }
+
+    #[test]
+    fn test_attribute_addr() {
+        let mut v = VramAddr::new();
+        v.set_nametable(1);
+        v.set_scroll_x(17 << 3);
+        v.set_scroll_y(9 << 3);
+
+        assert_eq!(v.attribute_addr(), 0x27c0 + 2 * 8 + 4);
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::{
+    palette::SYSTEM_PALETTE,
+    ppu::{NesPPU, PRE_RENDER_SCANLINE},
+};
+
+/// The background half of the rendering pipeline: the tile being fetched and the
+/// shift registers holding the two tiles being drawn.
+/// https://www.nesdev.org/wiki/PPU_rendering
+#[derive(Default)]
+pub struct BackgroundShifters {
+    next_tile: u8,
+    next_attribute: u8,
+    next_lower: u8,
+    next_upper: u8,
+    pattern_lower: u16,
+    pattern_upper: u16,
+    attribute_lower: u16,
+    attribute_upper: u16,
+}
+
+impl BackgroundShifters {
+    fn shift(&mut self) {
+        self.pattern_lower <<= 1;
+        self.pattern_upper <<= 1;
+        self.attribute_lower <<= 1;
+        self.attribute_upper <<= 1;
+    }
+
+    // The fetched tile goes into the low byte, behind the one being drawn
+    fn reload(&mut self) {
+        self.pattern_lower = (self.pattern_lower & 0xff00) | self.next_lower as u16;
+        self.pattern_upper = (self.pattern_upper & 0xff00) | self.next_upper as u16;
+        let spread = |bit: u8| if bit != 0 { 0xff } else { 0x00 };
+        self.attribute_lower = (self.attribute_lower & 0xff00) | spread(self.next_attribute & 0b01);
+        self.attribute_upper = (self.attribute_upper & 0xff00) | spread(self.next_attribute & 0b10);
+    }
+}
+
+impl Savestate for BackgroundShifters {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.next_tile);
+        w.u8(self.next_attribute);
+        w.u8(self.next_lower);
+        w.u8(self.next_upper);
+        w.u16(self.pattern_lower);
+        w.u16(self.pattern_upper);
+        w.u16(self.attribute_lower);
+        w.u16(self.attribute_upper);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.next_tile = r.u8()?;
+        self.next_attribute = r.u8()?;
+        self.next_lower = r.u8()?;
+        self.next_upper = r.u8()?;
+        self.pattern_lower = r.u16()?;
+        self.pattern_upper = r.u16()?;
+        self.attribute_lower = r.u16()?;
+        self.attribute_upper = r.u16()?;
+        Ok(())
+    }
+}
+
+impl NesPPU {
+    /// Runs the background fetches and the scroll updates of the current dot,
+    /// called on visible scanlines and the pre-render line while rendering is enabled.
+    pub(super) fn fetch_background(&mut self) {
+        let dot = self.cycles;
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.background.shift();
+
+            // Each fetch takes two dots, a tile takes eight
+            match (dot - 1) % 8 {
+                0 => {
+                    self.background.reload();
+                    self.background.next_tile = self.read_nametable(self.v.tile_addr());
+                }
+                2 => {
+                    let attribute = self.read_nametable(self.v.attribute_addr());
+                    // Each attribute byte covers 4x4 tiles, two bits per 2x2 quadrant
+                    let shift = ((self.v.coarse_y() & 2) << 1) | (self.v.coarse_x() & 2);
+                    self.background.next_attribute = (attribute >> shift) & 0b11;
+                }
+                4 => self.background.next_lower = self.read_background_pattern(0),
+                6 => self.background.next_upper = self.read_background_pattern(8),
+                7 => self.v.increment_x(),
+                _ => {}
+            }
+        }
+
+        if dot == 256 {
+            self.v.increment_y();
+        }
+        if dot == 257 {
+            self.v.copy_horizontal(self.t);
+        }
+        if self.scanline == PRE_RENDER_SCANLINE && (280..=304).contains(&dot) {
+            self.v.copy_vertical(self.t);
+        }
+    }
+
+    fn read_background_pattern(&self, plane: u16) -> u8 {
+        let addr = self.ctrl.background_pattern_addr()
+            + self.background.next_tile as u16 * 16
+            + self.v.fine_y()
+            + plane;
+        self.cartridge.borrow().read_chr(addr)
+    }
+
+    /// Outputs the pixel at column `x` of the current scanline, combining the
+    /// background with the sprites evaluated for this line.
+    pub(super) fn render_pixel(&mut self, x: usize) {
+        let y = self.scanline as usize;
+        let (bg_pixel, bg_palette) = self.background_pixel(x);
+
+        let (pixel, palette) = match self.sprite_pixel(x) {
+            Some(sprite) => {
+                // Only opaque pixels of both collide, never at the last column
+                if sprite.is_sprite_zero && bg_pixel != 0 && x != 255 {
+                    self.status.set_sprite_zero_hit(true);
+                }
+                if sprite.behind_background && bg_pixel != 0 {
+                    (bg_pixel, bg_palette)
+                } else {
+                    (sprite.pixel, sprite.palette)
+                }
+            }
+            None => (bg_pixel, bg_palette),
+        };
+
+        let color = self.palette_color(pixel, palette);
+        self.frame.set_pixel(x, y, color);
+    }
+
+    /// The 2-bit pattern value and the palette of the background at column `x`,
+    /// taken from the shift registers at fine X. A pattern value of 0 means transparent.
+    pub(super) fn background_pixel(&self, x: usize) -> (u8, u8) {
+        if !self.mask.show_background() || (x < 8 && !self.mask.show_background_leftmost()) {
+            return (0, 0);
+        }
+
+        let mux = 0x8000 >> self.fine_x;
+        let bit = |shifter: u16| (shifter & mux != 0) as u8;
+        let background = &self.background;
+        let pixel = (bit(background.pattern_upper) << 1) | bit(background.pattern_lower);
+        let palette = (bit(background.attribute_upper) << 1) | bit(background.attribute_lower);
+
+        (pixel, palette)
+    }
+
+    /// Looks a pixel up in palette RAM, background palettes are 0-3 and sprite palettes 4-7.
+    pub(super) fn palette_color(&self, pixel: u8, palette: u8) -> (u8, u8, u8) {
+        // Pattern value 0 of every palette shows the backdrop color
+        let index = if pixel == 0 {
+            0
+        } else {
+            palette as usize * 4 + pixel as usize
+        };
+        let mut color = self.palette_table[index] & 0x3f;
+        if self.mask.greyscale() {
+            color &= 0x30;
+        }
+        SYSTEM_PALETTE[color as usize]
+    }
+
+    fn read_nametable(&self, addr: u16) -> u8 {
+        self.vram[self.mirror_vram_addr(addr) as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        mapper::mapper::from_rom,
+        rom::{Mirroring, Rom},
+    };
+
+    const WHITE: u8 = 0x30;
+    const BLACK: u8 = 0x0f;
+    const RED: u8 = 0x16;
+    const BLUE: u8 = 0x21;
+
+    // Tile 1 is solid color 3, tile 2 solid color 1
+    fn ppu_with_tiles() -> NesPPU {
+        let mut rom = Rom::from_test_code(vec![]);
+        rom.screen_mirroring = Mirroring::VERTICAL;
+        rom.chr_rom = vec![0; 0x2000];
+        rom.chr_rom[16..32].copy_from_slice(&[0xff; 16]);
+        rom.chr_rom[32..40].copy_from_slice(&[0xff; 8]);
+        let mut ppu = NesPPU::new(from_rom(rom).unwrap());
+
+        ppu.palette_table[0] = BLACK;
+        ppu.palette_table[1] = BLUE;
+        ppu.palette_table[3] = WHITE;
+        ppu.palette_table[5] = RED;
+        ppu.write_to_mask(0b0000_1010);
+        ppu
+    }
+
+    // Starts on the pre-render line, so v is loaded from t and the first two tiles are fetched
+    fn render_first_scanline(ppu: &mut NesPPU) {
+        render_until(ppu, 0, 256);
+    }
+
+    fn render_until(ppu: &mut NesPPU, scanline: u16, dot: usize) {
+        ppu.scanline = PRE_RENDER_SCANLINE;
+        ppu.cycles = 0;
+        render_until_next(ppu, scanline, dot);
+    }
+
+    fn render_until_next(ppu: &mut NesPPU, scanline: u16, dot: usize) {
+        while !(ppu.scanline == scanline && ppu.cycles == dot) {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_background_tiles() {
+        let mut ppu = ppu_with_tiles();
+        ppu.vram[0] = 1;
+        // Tile 2 with palette 1 at column 2
+        ppu.vram[2] = 2;
+        ppu.vram[0x3c0] = 0b0000_0100;
+
+        render_first_scanline(&mut ppu);
+
+        assert_eq!(ppu.frame.pixel(0, 0), SYSTEM_PALETTE[WHITE as usize]);
+        assert_eq!(ppu.frame.pixel(7, 0), SYSTEM_PALETTE[WHITE as usize]);
+        assert_eq!(ppu.frame.pixel(8, 0), SYSTEM_PALETTE[BLACK as usize]);
+        assert_eq!(ppu.frame.pixel(16, 0), SYSTEM_PALETTE[RED as usize]);
+    }
+
+    #[test]
+    fn test_background_disabled_shows_backdrop() {
+        let mut ppu = ppu_with_tiles();
+        ppu.vram[0] = 1;
+        ppu.write_to_mask(0);
+
+        render_first_scanline(&mut ppu);
+
+        assert_eq!(ppu.frame.pixel(0, 0), SYSTEM_PALETTE[BLACK as usize]);
+    }
+
+    #[test]
+    fn test_background_hidden_in_leftmost_column() {
+        let mut ppu = ppu_with_tiles();
+        ppu.vram[0] = 1;
+        ppu.vram[1] = 1;
+        ppu.write_to_mask(0b0000_1000);
+
+        render_first_scanline(&mut ppu);
+
+        assert_eq!(ppu.frame.pixel(7, 0), SYSTEM_PALETTE[BLACK as usize]);
+        assert_eq!(ppu.frame.pixel(8, 0), SYSTEM_PALETTE[WHITE as usize]);
+    }
+
+    #[test]
+    fn test_scrolling_into_next_nametable() {
+        // With vertical mirroring the second nametable is at $0400 in VRAM
+        let mut ppu = ppu_with_tiles();
+        ppu.vram[31] = 1;
+        ppu.vram[0x400] = 2;
+        ppu.write_to_scroll(248);
+        ppu.write_to_scroll(0);
+
+        render_first_scanline(&mut ppu);
+
+        assert_eq!(ppu.frame.pixel(0, 0), SYSTEM_PALETTE[WHITE as usize]);
+        assert_eq!(ppu.frame.pixel(8, 0), SYSTEM_PALETTE[BLUE as usize]);
+    }
+
+    #[test]
+    fn test_fine_x_scroll() {
+        let mut ppu = ppu_with_tiles();
+        ppu.vram[0] = 1;
+        ppu.write_to_scroll(4);
+        ppu.write_to_scroll(0);
+
+        render_first_scanline(&mut ppu);
+
+        assert_eq!(ppu.frame.pixel(3, 0), SYSTEM_PALETTE[WHITE as usize]);
+        assert_eq!(ppu.frame.pixel(4, 0), SYSTEM_PALETTE[BLACK as usize]);
+    }
+
+    #[test]
+    fn test_fine_y_scroll() {
+        let mut ppu = ppu_with_tiles();
+        // Tile 1 on row 1 only
+        ppu.vram[32] = 1;
+        ppu.write_to_scroll(0);
+        ppu.write_to_scroll(3);
+
+        render_until(&mut ppu, 5, 1);
+
+        assert_eq!(ppu.frame.pixel(0, 4), SYSTEM_PALETTE[BLACK as usize]);
+        assert_eq!(ppu.frame.pixel(0, 5), SYSTEM_PALETTE[WHITE as usize]);
+    }
+
+    #[test]
+    fn test_mid_frame_scroll_split() {
+        let mut ppu = ppu_with_tiles();
+        // Tile 1 down column 1
+        for row in 0..30 {
+            ppu.vram[row * 32 + 1] = 1;
+        }
+
+        // Status bar above, scrolled playfield below
+        render_until(&mut ppu, 100, 100);
+        ppu.write_to_scroll(8);
+        ppu.write_to_scroll(0);
+        render_until_next(&mut ppu, 101, 256);
+
+        assert_eq!(ppu.frame.pixel(0, 100), SYSTEM_PALETTE[BLACK as usize]);
+        assert_eq!(ppu.frame.pixel(8, 100), SYSTEM_PALETTE[WHITE as usize]);
+        // Only coarse X and the horizontal nametable reach v at dot 257
+        assert_eq!(ppu.frame.pixel(0, 101), SYSTEM_PALETTE[WHITE as usize]);
+        assert_eq!(ppu.frame.pixel(8, 101), SYSTEM_PALETTE[BLACK as usize]);
+    }
+
+    #[test]
+    fn test_ppu_addr_write_moves_v_mid_frame() {
+        let mut ppu = ppu_with_tiles();
+        // Tile 1 on row 20 only
+        ppu.vram[20 * 32] = 1;
+
+        render_until(&mut ppu, 10, 300);
+        // Nametable 0, coarse Y 20, fine Y 0
+        ppu.write_to_ppu_addr(0x02);
+        ppu.write_to_ppu_addr(0x80);
+        render_until_next(&mut ppu, 11, 256);
+
+        assert_eq!(ppu.frame.pixel(0, 10), SYSTEM_PALETTE[BLACK as usize]);
+        assert_eq!(ppu.frame.pixel(0, 11), SYSTEM_PALETTE[WHITE as usize]);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::{
+    error::EmuError,
+    savestate::{Savestate, StateReader, StateWriter},
+};
+
+use super::ppu::NesPPU;
+
+const SPRITE_COUNT: usize = 64;
+const SPRITES_PER_SCANLINE: usize = 8;
+
+// OAM attribute bits
+const ATTR_PALETTE: u8 = 0b0000_0011;
+const ATTR_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const ATTR_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const ATTR_FLIP_VERTICAL: u8 = 0b1000_0000;
+
+/// A sprite picked by evaluation, with its pattern row for the scanline already fetched.
+#[derive(Default)]
+pub struct LineSprite {
+    x: u8,
+    attributes: u8,
+    lower: u8,
+    upper: u8,
+    is_sprite_zero: bool,
+}
+
+/// The opaque sprite pixel that wins at a screen position.
+pub struct SpritePixel {
+    pub pixel: u8,
+    // Sprite palettes are 4-7
+    pub palette: u8,
+    pub behind_background: bool,
+    pub is_sprite_zero: bool,
+}
+
+impl NesPPU {
+    fn sprite_height(&self) -> usize {
+        self.ctrl.sprite_size() as usize
+    }
+
+    fn sprite_in_range(&self, sprite_y: u8) -> bool {
+        let row = self.scanline as isize - sprite_y as isize;
+        row >= 0 && (row as usize) < self.sprite_height()
+    }
+
+    /// Scans OAM for the sprites on the next scanline, a sprite with Y = n is drawn
+    /// from scanline n + 1. Sets the overflow flag the way the hardware does,
+    /// including its diagonal OAM walk once 8 sprites were found.
+    /// https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    pub(super) fn evaluate_sprites(&mut self) {
+        let mut found = Vec::with_capacity(SPRITES_PER_SCANLINE);
+        let mut n = 0;
+        while n < SPRITE_COUNT && found.len() < SPRITES_PER_SCANLINE {
+            if self.sprite_in_range(self.oam_data[n * 4]) {
+                found.push(n);
+            }
+            n += 1;
+        }
+
+        if found.len() == SPRITES_PER_SCANLINE {
+            // The byte index is incremented along with the sprite index, so the
+            // PPU reads tile, attribute and X bytes as if they were Y coordinates
+            let mut m = 0;
+            for sprite in n..SPRITE_COUNT {
+                if self.sprite_in_range(self.oam_data[sprite * 4 + m]) {
+                    self.status.set_sprite_overflow(true);
+                    break;
+                }
+                m = (m + 1) & 3;
+            }
+
+            if self.no_sprite_limit {
+                found.extend(
+                    (n..SPRITE_COUNT).filter(|&i| self.sprite_in_range(self.oam_data[i * 4])),
+                );
+            }
+        }
+
+        self.line_sprites = found
+            .into_iter()
+            .map(|index| self.fetch_sprite(index))
+            .collect();
+    }
+
+    fn fetch_sprite(&self, index: usize) -> LineSprite {
+        let sprite_y = self.oam_data[index * 4];
+        let tile = self.oam_data[index * 4 + 1] as u16;
+        let attributes = self.oam_data[index * 4 + 2];
+        let x = self.oam_data[index * 4 + 3];
+
+        let height = self.sprite_height();
+        let mut row = self.scanline as usize - sprite_y as usize;
+        if attributes & ATTR_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+
+        let tile_addr = if height == 16 {
+            // 8x16 sprites pick the table with bit 0, the bottom half is the next tile
+            let table = (tile & 1) * 0x1000;
+            let tile = (tile & 0xfe) + (row / 8) as u16;
+            table + tile * 16
+        } else {
+            self.ctrl.sprite_pattern_addr() + tile * 16
+        };
+        let pattern_addr = tile_addr + (row % 8) as u16;
+
+        let cartridge = self.cartridge.borrow();
+        let mut lower = cartridge.read_chr(pattern_addr);
+        let mut upper = cartridge.read_chr(pattern_addr + 8);
+        if attributes & ATTR_FLIP_HORIZONTAL != 0 {
+            lower = lower.reverse_bits();
+            upper = upper.reverse_bits();
+        }
+
+        LineSprite {
+            x,
+            attributes,
+            lower,
+            upper,
+            is_sprite_zero: index == 0,
+        }
+    }
+
+    /// The first opaque sprite pixel at column `x` of the current scanline, in OAM order.
+    pub(super) fn sprite_pixel(&self, x: usize) -> Option<SpritePixel> {
+        if !self.mask.show_sprites() || (x < 8 && !self.mask.show_sprites_leftmost()) {
+            return None;
+        }
+
+        self.line_sprites.iter().find_map(|sprite| {
+            let column = x.checked_sub(sprite.x as usize).filter(|&c| c < 8)?;
+            let bit = 7 - column;
+            let pixel = (((sprite.upper >> bit) & 1) << 1) | ((sprite.lower >> bit) & 1);
+            if pixel == 0 {
+                return None;
+            }
+
+            Some(SpritePixel {
+                pixel,
+                palette: 4 + (sprite.attributes & ATTR_PALETTE),
+                behind_background: sprite.attributes & ATTR_BEHIND_BACKGROUND != 0,
+                is_sprite_zero: sprite.is_sprite_zero,
+            })
+        })
+    }
+}
+
+impl Savestate for LineSprite {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.x);
+        w.u8(self.attributes);
+        w.u8(self.lower);
+        w.u8(self.upper);
+        w.bool(self.is_sprite_zero);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError> {
+        self.x = r.u8()?;
+        self.attributes = r.u8()?;
+        self.lower = r.u8()?;
+        self.upper = r.u8()?;
+        self.is_sprite_zero = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mapper::mapper::from_rom, ppu::palette::SYSTEM_PALETTE, rom::Rom};
+
+    const BACKDROP: u8 = 0x0f;
+    const BG_COLOR: u8 = 0x30;
+    const SPRITE_COLOR: u8 = 0x16;
+    const OTHER_SPRITE_COLOR: u8 = 0x2a;
+
+    // Tile 1 is solid color 3, tile 2 has only its top left pixel set,
+    // tile 3 is solid color 1
+    fn ppu_with_sprites() -> NesPPU {
+        let mut rom = Rom::from_test_code(vec![]);
+        rom.chr_rom = vec![0; 0x2000];
+        rom.chr_rom[16..32].copy_from_slice(&[0xff; 16]);
+        rom.chr_rom[32] = 0b1000_0000;
+        rom.chr_rom[40] = 0b1000_0000;
+        rom.chr_rom[48..56].copy_from_slice(&[0xff; 8]);
+        let mut ppu = NesPPU::new(from_rom(rom).unwrap());
+
+        ppu.palette_table[0] = BACKDROP;
+        ppu.palette_table[3] = BG_COLOR;
+        ppu.palette_table[0x13] = SPRITE_COLOR;
+        ppu.palette_table[0x17] = OTHER_SPRITE_COLOR;
+        ppu.palette_table[0x11] = OTHER_SPRITE_COLOR;
+        // Move every sprite off screen
+        ppu.oam_data = [0xff; 256];
+        // Background and sprites, including the leftmost 8 pixels
+        ppu.write_to_mask(0b0001_1110);
+        ppu
+    }
+
+    fn set_sprite(ppu: &mut NesPPU, index: usize, y: u8, tile: u8, attributes: u8, x: u8) {
+        ppu.oam_data[index * 4..index * 4 + 4].copy_from_slice(&[y, tile, attributes, x]);
+    }
+
+    // Runs until the given scanline has been drawn
+    fn render_until(ppu: &mut NesPPU, scanline: u16) {
+        while ppu.scanline <= scanline {
+            ppu.tick(1);
+        }
+    }
+
+    fn color(ppu: &NesPPU, x: usize, y: usize) -> (u8, u8, u8) {
+        ppu.frame.pixel(x, y)
+    }
+
+    #[test]
+    fn test_sprite_is_drawn_one_line_below_its_y() {
+        let mut ppu = ppu_with_sprites();
+        set_sprite(&mut ppu, 0, 10, 1, 0, 20);
+
+        render_until(&mut ppu, 18);
+
+        assert_eq!(color(&ppu, 20, 10), SYSTEM_PALETTE[BACKDROP as usize]);
+        assert_eq!(color(&ppu, 20, 11), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+        assert_eq!(color(&ppu, 27, 18), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+        assert_eq!(color(&ppu, 28, 18), SYSTEM_PALETTE[BACKDROP as usize]);
+        assert_eq!(color(&ppu, 19, 11), SYSTEM_PALETTE[BACKDROP as usize]);
+    }
+
+    #[test]
+    fn test_sprite_flipping() {
+        let mut ppu = ppu_with_sprites();
+        set_sprite(&mut ppu, 0, 10, 2, 0, 20);
+        set_sprite(
+            &mut ppu,
+            1,
+            10,
+            2,
+            ATTR_FLIP_HORIZONTAL | ATTR_FLIP_VERTICAL,
+            40,
+        );
+
+        render_until(&mut ppu, 18);
+
+        assert_eq!(color(&ppu, 20, 11), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+        assert_eq!(color(&ppu, 40, 11), SYSTEM_PALETTE[BACKDROP as usize]);
+        assert_eq!(color(&ppu, 47, 18), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+    }
+
+    #[test]
+    fn test_lower_oam_index_wins() {
+        let mut ppu = ppu_with_sprites();
+        set_sprite(&mut ppu, 3, 10, 1, 0, 20);
+        set_sprite(&mut ppu, 5, 10, 1, 1, 24);
+
+        render_until(&mut ppu, 11);
+
+        assert_eq!(color(&ppu, 24, 11), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+        assert_eq!(
+            color(&ppu, 28, 11),
+            SYSTEM_PALETTE[OTHER_SPRITE_COLOR as usize]
+        );
+    }
+
+    #[test]
+    fn test_sprite_behind_background() {
+        let mut ppu = ppu_with_sprites();
+        // Background tile 1 at the top left tile only
+        ppu.vram[0] = 1;
+        set_sprite(&mut ppu, 0, 0, 1, ATTR_BEHIND_BACKGROUND, 4);
+
+        render_until(&mut ppu, 1);
+
+        assert_eq!(color(&ppu, 4, 1), SYSTEM_PALETTE[BG_COLOR as usize]);
+        assert_eq!(color(&ppu, 8, 1), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+    }
+
+    #[test]
+    fn test_sprites_hidden_in_leftmost_column() {
+        let mut ppu = ppu_with_sprites();
+        set_sprite(&mut ppu, 0, 10, 1, 0, 4);
+        ppu.write_to_mask(0b0001_1010);
+
+        render_until(&mut ppu, 11);
+
+        assert_eq!(color(&ppu, 7, 11), SYSTEM_PALETTE[BACKDROP as usize]);
+        assert_eq!(color(&ppu, 8, 11), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let mut ppu = ppu_with_sprites();
+        ppu.write_to_ctrl(0b0010_0000);
+        // Tiles 2 and 3 from $0000
+        set_sprite(&mut ppu, 0, 10, 2, 0, 20);
+
+        render_until(&mut ppu, 27);
+
+        assert_eq!(color(&ppu, 20, 11), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+        assert_eq!(color(&ppu, 21, 11), SYSTEM_PALETTE[BACKDROP as usize]);
+        assert_eq!(
+            color(&ppu, 21, 19),
+            SYSTEM_PALETTE[OTHER_SPRITE_COLOR as usize]
+        );
+        assert_eq!(color(&ppu, 21, 27), SYSTEM_PALETTE[BACKDROP as usize]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = ppu_with_sprites();
+        ppu.vram[0] = 1;
+        set_sprite(&mut ppu, 0, 4, 1, 0, 6);
+
+        render_until(&mut ppu, 4);
+        assert!(!ppu.status.is_sprite_zero_hit());
+
+        // Overlap starts at dot 7 of scanline 5
+        while ppu.cycles < 7 {
+            ppu.tick(1);
+        }
+        assert!(ppu.status.is_sprite_zero_hit());
+
+        // Cleared when the next frame starts
+        while ppu.scanline != 0 {
+            ppu.tick(1);
+        }
+        assert!(!ppu.status.is_sprite_zero_hit());
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_on_transparent_background() {
+        let mut ppu = ppu_with_sprites();
+        set_sprite(&mut ppu, 0, 4, 1, 0, 6);
+
+        render_until(&mut ppu, 12);
+
+        assert!(!ppu.status.is_sprite_zero_hit());
+    }
+
+    #[test]
+    fn test_eight_sprites_per_scanline() {
+        let mut ppu = ppu_with_sprites();
+        for i in 0..9 {
+            set_sprite(&mut ppu, i, 10, 1, 0, i as u8 * 10);
+        }
+
+        render_until(&mut ppu, 11);
+
+        assert_eq!(color(&ppu, 70, 11), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+        assert_eq!(color(&ppu, 80, 11), SYSTEM_PALETTE[BACKDROP as usize]);
+        assert!(ppu.status.is_sprite_overflow());
+    }
+
+    #[test]
+    fn test_no_sprite_limit() {
+        let mut ppu = ppu_with_sprites();
+        ppu.no_sprite_limit = true;
+        for i in 0..9 {
+            set_sprite(&mut ppu, i, 10, 1, 0, i as u8 * 10);
+        }
+
+        render_until(&mut ppu, 11);
+
+        assert_eq!(color(&ppu, 80, 11), SYSTEM_PALETTE[SPRITE_COLOR as usize]);
+        // The flag still behaves like the hardware
+        assert!(ppu.status.is_sprite_overflow());
+    }
+
+    #[test]
+    fn test_sprite_overflow_hardware_bug() {
+        let mut ppu = ppu_with_sprites();
+        for i in 0..8 {
+            set_sprite(&mut ppu, i, 10, 1, 0, 0);
+        }
+        // The 9th sprite is out of range, but the diagonal walk reads the tile byte
+        // of the 10th sprite as its Y coordinate
+        set_sprite(&mut ppu, 8, 100, 1, 0, 0);
+        set_sprite(&mut ppu, 9, 100, 10, 0, 0);
+
+        render_until(&mut ppu, 10);
+        assert!(ppu.status.is_sprite_overflow());
+
+        // Nine sprites on a line can also be missed
+        let mut ppu = ppu_with_sprites();
+        for i in 0..8 {
+            set_sprite(&mut ppu, i, 10, 1, 0, 0);
+        }
+        set_sprite(&mut ppu, 8, 100, 1, 0, 0);
+        set_sprite(&mut ppu, 9, 10, 100, 0, 0);
+
+        render_until(&mut ppu, 10);
+        assert!(!ppu.status.is_sprite_overflow());
+    }
+}
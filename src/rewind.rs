@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+/// Ring buffer of per-frame save states for rewinding.
+///
+/// States are grouped behind a keyframe: the keyframe is stored whole and
+/// every following state as its XOR against the keyframe, which is mostly
+/// zeros since little changes between frames. Both are run length encoded.
+/// Once the buffer goes over its memory budget the oldest group is dropped.
+pub struct Rewind {
+    budget: usize,
+    keyframe_interval: usize,
+    groups: VecDeque<Group>,
+    memory_used: usize,
+}
+
+struct Group {
+    // Uncompressed, new deltas are made against it
+    keyframe: Vec<u8>,
+    compressed_keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        self.compressed_keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+impl Rewind {
+    /// `budget` is in bytes of compressed states, a keyframe is taken every `keyframe_interval` states.
+    pub fn new(budget: usize, keyframe_interval: usize) -> Self {
+        Rewind {
+            budget,
+            keyframe_interval: keyframe_interval.max(1),
+            groups: VecDeque::new(),
+            memory_used: 0,
+        }
+    }
+
+    pub fn push(&mut self, state: &[u8]) {
+        match self.groups.back_mut() {
+            Some(group) if group.deltas.len() + 1 < self.keyframe_interval => {
+                let delta = compress(&xor(state, &group.keyframe));
+                self.memory_used += delta.len();
+                group.deltas.push(delta);
+            }
+            _ => {
+                let compressed_keyframe = compress(state);
+                self.memory_used += compressed_keyframe.len();
+                self.groups.push_back(Group {
+                    keyframe: state.to_vec(),
+                    compressed_keyframe,
+                    deltas: vec![],
+                });
+            }
+        }
+
+        // The newest group stays, even if it alone is over the budget
+        while self.memory_used > self.budget && self.groups.len() > 1 {
+            let oldest = self.groups.pop_front().unwrap();
+            self.memory_used -= oldest.size();
+        }
+    }
+
+    /// Takes the newest state off the buffer.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let group = self.groups.back_mut()?;
+        let state = match group.deltas.pop() {
+            Some(delta) => {
+                self.memory_used -= delta.len();
+                xor(&decompress(&delta), &group.keyframe)
+            }
+            None => {
+                let group = self.groups.pop_back().unwrap();
+                self.memory_used -= group.compressed_keyframe.len();
+                group.keyframe
+            }
+        };
+        Some(state)
+    }
+
+    /// Number of states that can be stepped back through
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|group| 1 + group.deltas.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+}
+
+// The length of `state` wins, missing keyframe bytes count as zeros. States can
+// differ in length, the PPU only saves the sprites it found on the current line.
+fn xor(state: &[u8], keyframe: &[u8]) -> Vec<u8> {
+    let mut delta = state.to_vec();
+    for (byte, key) in delta.iter_mut().zip(keyframe) {
+        *byte ^= key;
+    }
+    delta
+}
+
+// A zero is followed by the length of its run as a LEB128 varint, other bytes
+// stand for themselves.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 4);
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != 0 {
+            out.push(data[i]);
+            i += 1;
+            continue;
+        }
+        let run = data[i..].iter().take_while(|&&byte| byte == 0).count();
+        i += run;
+        out.push(0);
+        let mut run = run;
+        while run >= 0x80 {
+            out.push(run as u8 | 0x80);
+            run >>= 7;
+        }
+        out.push(run as u8);
+    }
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != 0 {
+            out.push(byte);
+            continue;
+        }
+        let mut run = 0;
+        for (shift, &byte) in (0..).step_by(7).zip(bytes.by_ref()) {
+            run |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        out.resize(out.len() + run, 0);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Mostly zeros with a frame counter and some noise, like RAM between two frames
+    fn state(frame: u8) -> Vec<u8> {
+        let mut state = vec![0; 4096];
+        state[0] = frame;
+        for i in (100..state.len()).step_by(97) {
+            state[i] = (i as u8).wrapping_mul(31);
+        }
+        state[2000 + frame as usize] = 0xff;
+        state
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let mut data = vec![0; 600];
+        data.extend([1, 2, 0, 3, 0, 0]);
+        let compressed = compress(&data);
+
+        // 600 = 0b100_1011000
+        assert_eq!(&compressed[..3], &[0, 0xd8, 0x04]);
+        assert_eq!(decompress(&compressed), data);
+        assert!(decompress(&compress(&[])).is_empty());
+    }
+
+    #[test]
+    fn test_pops_newest_first() {
+        let mut rewind = Rewind::new(usize::MAX, 4);
+        for frame in 0..10 {
+            rewind.push(&state(frame));
+        }
+        assert_eq!(rewind.len(), 10);
+
+        for frame in (0..10).rev() {
+            assert_eq!(rewind.pop(), Some(state(frame)));
+        }
+        assert_eq!(rewind.pop(), None);
+        assert_eq!(rewind.memory_used(), 0);
+    }
+
+    #[test]
+    fn test_deltas_are_small() {
+        let mut rewind = Rewind::new(usize::MAX, 60);
+        rewind.push(&state(0));
+        let keyframe_size = rewind.memory_used();
+        rewind.push(&state(1));
+
+        assert!(rewind.memory_used() - keyframe_size < 16);
+        assert!(keyframe_size < 4096 / 4);
+    }
+
+    #[test]
+    fn test_states_of_different_lengths() {
+        let mut rewind = Rewind::new(usize::MAX, 60);
+        let long = vec![7; 20];
+        rewind.push(&[1, 2, 3]);
+        rewind.push(&long);
+        rewind.push(&[]);
+
+        assert_eq!(rewind.pop(), Some(vec![]));
+        assert_eq!(rewind.pop(), Some(long));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_budget_drops_oldest_group() {
+        let mut rewind = Rewind::new(usize::MAX, 5);
+        for frame in 0..5 {
+            rewind.push(&state(frame));
+        }
+        let group_size = rewind.memory_used();
+
+        let mut rewind = Rewind::new(group_size * 5 / 2, 5);
+        for frame in 0..20 {
+            rewind.push(&state(frame));
+        }
+
+        assert!(rewind.memory_used() <= group_size * 5 / 2);
+        assert_eq!(rewind.len(), 10);
+        assert_eq!(rewind.pop(), Some(state(19)));
+    }
+}
@@ -1,32 +1,85 @@
+use crate::error::EmuError;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+const PRG_RAM_DEFAULT_SIZE: usize = 8192;
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
     FourScreen,
+    // All four nametables show the first/second 1KiB of VRAM
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HeaderFormat {
+    INes,
+    Nes2,
+}
+
+/// CPU/PPU timing the ROM was made for (NES 2.0 byte 12)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/// NES 2.0 byte 7 bits 0-1, and byte 13 for the extended types
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    Extended(u8),
 }
 
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    pub mapper: u16,
+    pub submapper: u8,
     pub screen_mirroring: Mirroring,
+    pub format: HeaderFormat,
+    // RAM sizes in bytes, the NVRAM ones are battery backed
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    // Byte 6 bit 1, PRG-RAM keeps its contents while the console is off
+    pub battery: bool,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    pub expansion_device: u8,
 }
 
 impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+    pub fn new(raw: &Vec<u8>) -> Result<Rom, EmuError> {
+        if raw.len() < NES_TAG.len() || raw[0..4] != NES_TAG {
+            return Err(EmuError::BadMagic);
+        }
+        if raw.len() < HEADER_SIZE {
+            return Err(EmuError::TruncatedHeader);
         }
 
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
-        let ines_ver = (raw[7] >> 2) & 0b11;
+        let format = match (raw[7] >> 2) & 0b11 {
+            2 => HeaderFormat::Nes2,
+            _ => HeaderFormat::INes,
+        };
+        let nes2 = format == HeaderFormat::Nes2;
 
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+        let mut mapper = ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16;
+        let mut submapper = 0;
+        if nes2 {
+            mapper |= ((raw[8] & 0b1111) as u16) << 8;
+            submapper = raw[8] >> 4;
         }
 
         let four_screen = raw[6] & 0b1000 != 0;
@@ -37,19 +90,99 @@ impl Rom {
             (false, false) => Mirroring::HORIZONTAL,
         };
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let (prg_rom_size, chr_rom_size) = if nes2 {
+            (
+                nes2_rom_size(raw[4], raw[9] & 0b1111, PRG_ROM_PAGE_SIZE),
+                nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE),
+            )
+        } else {
+            (
+                raw[4] as usize * PRG_ROM_PAGE_SIZE,
+                raw[5] as usize * CHR_ROM_PAGE_SIZE,
+            )
+        };
         let skip_trainer = raw[6] & 0b100 != 0;
+        let battery = raw[6] & 0b10 != 0;
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let prg_rom_start = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        // Sizes from a broken header can be near usize::MAX, they saturate
+        // instead of wrapping and then fail the length checks below
+        let chr_rom_start = prg_rom_start.saturating_add(prg_rom_size);
+        let chr_rom_end = chr_rom_start.saturating_add(chr_rom_size);
 
-        Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+        if raw.len() < chr_rom_start {
+            return Err(EmuError::TruncatedPrg {
+                expected: prg_rom_size,
+                actual: raw.len().saturating_sub(prg_rom_start),
+            });
+        }
+        if raw.len() < chr_rom_end {
+            return Err(EmuError::TruncatedChr {
+                expected: chr_rom_size,
+                actual: raw.len() - chr_rom_start,
+            });
+        }
+
+        let mut rom = Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..chr_rom_end].to_vec(),
             mapper: mapper,
+            submapper,
             screen_mirroring: screen_mirroring,
-        })
+            format,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: if chr_rom_size == 0 {
+                CHR_ROM_PAGE_SIZE
+            } else {
+                0
+            },
+            chr_nvram_size: 0,
+            battery,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+            expansion_device: 0,
+        };
+
+        if nes2 {
+            rom.prg_ram_size = nes2_ram_size(raw[10] & 0b1111);
+            rom.prg_nvram_size = nes2_ram_size(raw[10] >> 4);
+            rom.chr_ram_size = nes2_ram_size(raw[11] & 0b1111);
+            rom.chr_nvram_size = nes2_ram_size(raw[11] >> 4);
+            rom.timing = match raw[12] & 0b11 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            };
+            rom.console_type = match raw[7] & 0b11 {
+                0 => ConsoleType::Nes,
+                1 => ConsoleType::VsSystem,
+                2 => ConsoleType::Playchoice10,
+                _ => ConsoleType::Extended(raw[13] & 0b1111),
+            };
+            rom.expansion_device = raw[15] & 0b0011_1111;
+        } else {
+            // iNES 1.0 counts 8KiB units in byte 8, where 0 means one unit
+            let prg_ram_size = (raw[8] as usize).max(1) * PRG_RAM_DEFAULT_SIZE;
+            if battery {
+                rom.prg_nvram_size = prg_ram_size;
+            } else {
+                rom.prg_ram_size = prg_ram_size;
+            }
+        }
+
+        Ok(rom)
+    }
+
+    /// FNV-1a hash of PRG-ROM and CHR-ROM, identifies the game in save states.
+    pub fn hash(&self) -> u64 {
+        self.prg_rom
+            .iter()
+            .chain(&self.chr_rom)
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
     }
 
     #[cfg(test)]
@@ -68,7 +201,205 @@ impl Rom {
             prg_rom,
             chr_rom: vec![0; 0x2000], // dummy CHR-ROM
             mapper: 0,
+            submapper: 0,
             screen_mirroring: Mirroring::HORIZONTAL,
+            format: HeaderFormat::INes,
+            prg_ram_size: PRG_RAM_DEFAULT_SIZE,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            battery: false,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+            expansion_device: 0,
         }
     }
 }
+
+/// NES 2.0 ROM size: a plain 12-bit page count, or when the MSB nibble is $F,
+/// `2^E * (MM*2+1)` bytes from the LSB byte laid out as EEEEEEMM.
+/// Sizes that don't fit in a usize come out as usize::MAX.
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0b1111 {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .unwrap_or(usize::MAX)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * page_size
+    }
+}
+
+/// NES 2.0 RAM size: a shift count of 0 means none, otherwise 64 << shift bytes.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        let rom = Rom::from_test_code(vec![0xea]);
+        let other = Rom::from_test_code(vec![0xe8]);
+
+        assert_eq!(rom.hash(), Rom::from_test_code(vec![0xea]).hash());
+        assert_ne!(rom.hash(), other.hash());
+    }
+
+    fn header(bytes: [u8; 12]) -> Vec<u8> {
+        let mut raw = NES_TAG.to_vec();
+        raw.extend_from_slice(&bytes);
+        raw
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let mut raw = header([1, 1, 0b0001_0001, 0b0100_0000, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.extend(vec![0xaa; PRG_ROM_PAGE_SIZE]);
+        raw.extend(vec![0xbb; CHR_ROM_PAGE_SIZE]);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, HeaderFormat::INes);
+        assert_eq!(rom.mapper, 0x41);
+        assert_eq!(rom.submapper, 0);
+        assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+        assert_eq!(rom.prg_rom, vec![0xaa; PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.chr_rom, vec![0xbb; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.prg_ram_size, PRG_RAM_DEFAULT_SIZE);
+        assert_eq!(rom.prg_nvram_size, 0);
+        assert!(!rom.battery);
+        assert_eq!(rom.chr_ram_size, 0);
+        assert_eq!(rom.timing, Timing::Ntsc);
+    }
+
+    #[test]
+    fn test_ines_battery() {
+        let mut raw = header([1, 1, 0b0000_0010, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        raw.extend(vec![0; PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE]);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 2 * PRG_RAM_DEFAULT_SIZE);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut raw = header([
+            2,           // PRG-ROM LSB
+            0,           // CHR-ROM LSB
+            0b0100_0000, // mapper D0-3
+            0b0001_1001, // mapper D4-7, NES 2.0, Vs. System
+            0b0011_0001, // submapper 3, mapper D8-11
+            0,           // ROM size MSBs
+            0b0111_0000, // 8KiB PRG-NVRAM
+            0b0000_0111, // 8KiB CHR-RAM
+            1,           // PAL
+            0,
+            0,
+            0x21, // expansion device
+        ]);
+        raw.extend(vec![0; 2 * PRG_ROM_PAGE_SIZE]);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, HeaderFormat::Nes2);
+        assert_eq!(rom.mapper, 0x114);
+        assert_eq!(rom.submapper, 3);
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.chr_nvram_size, 0);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom.console_type, ConsoleType::VsSystem);
+        assert_eq!(rom.expansion_device, 0x21);
+    }
+
+    #[test]
+    fn test_nes2_oversized_rom() {
+        // PRG-ROM of 2^63 * 3 bytes
+        let mut raw = header([
+            0b1111_1101,
+            0,
+            0,
+            0b0000_1000,
+            0,
+            0b0000_1111,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        raw.extend(vec![0; PRG_ROM_PAGE_SIZE]);
+
+        assert!(matches!(Rom::new(&raw), Err(EmuError::TruncatedPrg { .. })));
+
+        // 2^63 bytes each, the CHR end is past usize::MAX
+        raw[4] = 0b1111_1100;
+        raw[5] = 0b1111_1100;
+        raw[9] = 0b1111_1111;
+        assert!(Rom::new(&raw).is_err());
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        assert_eq!(Rom::new(&vec![]).err(), Some(EmuError::BadMagic));
+        assert_eq!(Rom::new(&vec![0; 16]).err(), Some(EmuError::BadMagic));
+        assert_eq!(
+            Rom::new(&NES_TAG.to_vec()).err(),
+            Some(EmuError::TruncatedHeader)
+        );
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        let mut raw = header([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.extend(vec![0; PRG_ROM_PAGE_SIZE]);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(EmuError::TruncatedPrg {
+                expected: 2 * PRG_ROM_PAGE_SIZE,
+                actual: PRG_ROM_PAGE_SIZE
+            })
+        );
+
+        raw.extend(vec![0; PRG_ROM_PAGE_SIZE + 100]);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(EmuError::TruncatedChr {
+                expected: CHR_ROM_PAGE_SIZE,
+                actual: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_extended_console_type() {
+        let mut raw = header([1, 0, 0, 0b0000_1011, 0, 0, 0, 0, 0, 3, 0, 0]);
+        raw.extend(vec![0; PRG_ROM_PAGE_SIZE]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.console_type, ConsoleType::Extended(3));
+    }
+
+    #[test]
+    fn test_nes2_rom_sizes() {
+        assert_eq!(
+            nes2_rom_size(0x02, 0x1, PRG_ROM_PAGE_SIZE),
+            0x102 * PRG_ROM_PAGE_SIZE
+        );
+        // 2^4 * 3
+        assert_eq!(nes2_rom_size(0b0001_0001, 0xF, PRG_ROM_PAGE_SIZE), 48);
+        assert_eq!(nes2_ram_size(0), 0);
+        assert_eq!(nes2_ram_size(1), 128);
+    }
+}
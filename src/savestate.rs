@@ -0,0 +1,279 @@
+use std::path::{Path, PathBuf};
+
+use crate::{cpu::cpu::CPU, error::EmuError};
+
+const MAGIC: &[u8; 4] = b"NESS";
+/// Bumped whenever the layout of any component changes
+pub const VERSION: u16 = 1;
+// Magic, version and ROM hash
+const HEADER_SIZE: usize = 4 + 2 + 8;
+
+/// # Save state format
+///
+/// Everything is little endian:
+///
+///  "NESS"      magic
+///  u16         format version
+///  u64         hash of the ROM the state was made with
+///  ...         CPU, then the bus with RAM, PPU, APU, joypads and cartridge
+///
+/// Components write their fields in declaration order and leave out what
+/// the ROM or the frontend decides, like PRG-ROM or the sprite limit setting.
+pub trait Savestate {
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), EmuError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    /// Memory whose size is fixed by the ROM, so no length is stored
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], EmuError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| EmuError::InvalidSaveState("state is truncated".to_string()))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, EmuError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, EmuError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, EmuError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, EmuError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, EmuError> {
+        Ok(self.u64()? as usize)
+    }
+
+    /// Reads back what `StateWriter::bytes` wrote, `out` decides the length
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), EmuError> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    /// A value that has to be one of the variants the reader knows about
+    pub fn invalid(what: &str, value: impl std::fmt::Display) -> EmuError {
+        EmuError::InvalidSaveState(format!("{} {} is out of range", what, value))
+    }
+
+    pub fn finish(self) -> Result<(), EmuError> {
+        if self.pos == self.data.len() {
+            Ok(())
+        } else {
+            Err(EmuError::InvalidSaveState(format!(
+                "{} unexpected bytes at the end",
+                self.data.len() - self.pos
+            )))
+        }
+    }
+}
+
+/// File of a numbered save slot, next to the ROM: `game.nes` keeps slot 1 in `game.ss1`.
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path.with_extension(format!("ss{}", slot))
+}
+
+impl CPU {
+    /// Snapshots the whole machine.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u64(self.bus.rom_hash);
+        self.save(&mut w);
+        w.into_bytes()
+    }
+
+    /// Restores a snapshot made by `save_state` for the same ROM. On error the
+    /// machine is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        let mut r = StateReader::new(data);
+        let mut magic = [0; 4];
+        r.bytes(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(EmuError::InvalidSaveState("not a save state".to_string()));
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(EmuError::UnsupportedSaveStateVersion(version));
+        }
+        if r.u64()? != self.bus.rom_hash {
+            return Err(EmuError::SaveStateRomMismatch);
+        }
+
+        let backup = self.save_state();
+        // CPU::load also names the test helper that loads a program
+        let result = Savestate::load(self, &mut r).and_then(|_| r.finish());
+        if result.is_err() {
+            let mut r = StateReader::new(&backup[HEADER_SIZE..]);
+            Savestate::load(self, &mut r).expect("restoring the backup state failed");
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bus::Bus, headless, rom::Rom};
+
+    // Scrolls the screen and plays a note, so the PPU and APU state keep changing
+    fn busy_cpu() -> CPU {
+        let code = vec![
+            0xa9, 0x01, 0x8d, 0x15, 0x40, // LDA #$01, STA $4015
+            0xa9, 0x9f, 0x8d, 0x00, 0x40, // LDA #$9F, STA $4000
+            0xa9, 0xfd, 0x8d, 0x02, 0x40, // LDA #$FD, STA $4002
+            0xa9, 0x08, 0x8d, 0x03, 0x40, // LDA #$08, STA $4003
+            0xa9, 0x1e, 0x8d, 0x01, 0x20, // LDA #$1E, STA $2001
+            0xe6, 0x10, // loop: INC $10
+            0xa5, 0x10, // LDA $10
+            0x8d, 0x05, 0x20, // STA $2005
+            0x8d, 0x07, 0x20, // STA $2007
+            0x4c, 0x19, 0x80, // JMP loop
+        ];
+        let mut cpu = CPU::new(Bus::new(Rom::from_test_code(code)).unwrap());
+        cpu.reset();
+        cpu
+    }
+
+    fn run_frames(cpu: &mut CPU, frames: usize) {
+        assert_eq!(headless::run_frames(cpu, frames, |_| {}), Ok(frames));
+    }
+
+    #[test]
+    fn test_slot_path() {
+        assert_eq!(
+            slot_path(Path::new("roms/game.nes"), 3),
+            Path::new("roms/game.ss3")
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut cpu = busy_cpu();
+        run_frames(&mut cpu, 3);
+        let state = cpu.save_state();
+
+        let mut restored = busy_cpu();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.program_counter, cpu.program_counter);
+        assert_eq!(restored.cycles, cpu.cycles);
+    }
+
+    #[test]
+    fn test_deterministic_after_restore() {
+        let mut cpu = busy_cpu();
+        run_frames(&mut cpu, 2);
+        let state = cpu.save_state();
+        run_frames(&mut cpu, 5);
+
+        let mut restored = busy_cpu();
+        restored.load_state(&state).unwrap();
+        run_frames(&mut restored, 5);
+
+        assert_eq!(restored.save_state(), cpu.save_state());
+        assert_eq!(restored.bus.ppu.frame.data, cpu.bus.ppu.frame.data);
+    }
+
+    #[test]
+    fn test_rejects_other_rom() {
+        let state = busy_cpu().save_state();
+        let mut other = CPU::new(Bus::new(Rom::from_test_code(vec![0xea])).unwrap());
+
+        assert_eq!(
+            other.load_state(&state),
+            Err(EmuError::SaveStateRomMismatch)
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        let mut cpu = busy_cpu();
+        let mut state = cpu.save_state();
+
+        assert!(matches!(
+            cpu.load_state(b"junk"),
+            Err(EmuError::InvalidSaveState(_))
+        ));
+        state[4] = 0xff;
+        assert_eq!(
+            cpu.load_state(&state),
+            Err(EmuError::UnsupportedSaveStateVersion(0x00ff))
+        );
+    }
+
+    #[test]
+    fn test_truncated_state_leaves_machine_alone() {
+        let mut cpu = busy_cpu();
+        run_frames(&mut cpu, 1);
+        let state = cpu.save_state();
+        run_frames(&mut cpu, 1);
+        let before = cpu.save_state();
+
+        assert!(matches!(
+            cpu.load_state(&state[..state.len() - 10]),
+            Err(EmuError::InvalidSaveState(_))
+        ));
+        assert_eq!(cpu.save_state(), before);
+    }
+}
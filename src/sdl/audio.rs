@@ -0,0 +1,66 @@
+use sdl2::{
+    AudioSubsystem,
+    audio::{AudioQueue, AudioSpecDesired},
+};
+
+use crate::apu::{apu::ChannelOutput, mixer, sound::SoundPipeline};
+
+const SAMPLE_RATE: i32 = 48_000;
+// Queue length the rate control steers towards
+const TARGET_LATENCY_MS: u32 = 50;
+// Largest change to the resampling rate, small enough not to be heard as a pitch change
+const MAX_RATE_DELTA: f64 = 0.005;
+
+pub struct Audio {
+    queue: AudioQueue<f32>,
+    pipeline: SoundPipeline,
+    samples: Vec<f32>,
+    target_queued: usize,
+}
+
+impl Audio {
+    /// Opens a mono output at 48 kHz, or whatever rate the device offers instead.
+    pub fn open(audio_subsystem: &AudioSubsystem) -> Result<Self, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+        let queue = audio_subsystem.open_queue::<f32, _>(None, &desired)?;
+        let sample_rate = queue.spec().freq as u32;
+        queue.resume();
+
+        Ok(Audio {
+            queue,
+            pipeline: SoundPipeline::new(sample_rate),
+            samples: vec![],
+            target_queued: (sample_rate * TARGET_LATENCY_MS / 1000) as usize,
+        })
+    }
+
+    /// Mixes and queues the APU output of a frame.
+    ///
+    /// Vsync paces the emulation at the display refresh rate, which never quite
+    /// matches the audio clock, so the resampling rate is nudged to keep the
+    /// queue around its target instead of letting it run dry or pile up.
+    pub fn queue(&mut self, output: &[ChannelOutput]) {
+        let queued = self.queue.size() as usize / std::mem::size_of::<f32>();
+        let fill = queued as f64 / self.target_queued as f64;
+        let adjustment = 1.0 + MAX_RATE_DELTA * (1.0 - fill).clamp(-1.0, 1.0);
+        self.pipeline.set_rate_adjustment(adjustment);
+
+        for &channels in output {
+            self.pipeline.push(mixer::mix(channels));
+        }
+        self.samples.clear();
+        self.pipeline.read_samples(&mut self.samples);
+
+        // After a long stall, drop the backlog rather than play it late
+        if queued > 4 * self.target_queued {
+            self.queue.clear();
+        }
+        if let Err(err) = self.queue.queue_audio(&self.samples) {
+            eprintln!("Could not queue audio: {}", err);
+        }
+    }
+}
@@ -1,71 +1,116 @@
-use sdl2::{EventPump, event::Event, keyboard::Keycode, pixels::Color};
+use sdl2::{EventPump, event::Event, keyboard::Keycode};
 
-use crate::cpu::{cpu::CPU, memory::Mem};
+use crate::joypad::{Joypad, JoypadButton};
 
-pub fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
+// Player 1 on the arrows and letters, player 2 on the numeric keypad
+fn player1_button(key: Keycode) -> Option<JoypadButton> {
+    match key {
+        Keycode::Up => Some(JoypadButton::UP),
+        Keycode::Down => Some(JoypadButton::DOWN),
+        Keycode::Left => Some(JoypadButton::LEFT),
+        Keycode::Right => Some(JoypadButton::RIGHT),
+        Keycode::Space => Some(JoypadButton::SELECT),
+        Keycode::Return => Some(JoypadButton::START),
+        Keycode::A => Some(JoypadButton::BUTTON_A),
+        Keycode::S => Some(JoypadButton::BUTTON_B),
+        _ => None,
+    }
+}
+
+fn player2_button(key: Keycode) -> Option<JoypadButton> {
+    match key {
+        Keycode::Kp8 => Some(JoypadButton::UP),
+        Keycode::Kp5 => Some(JoypadButton::DOWN),
+        Keycode::Kp4 => Some(JoypadButton::LEFT),
+        Keycode::Kp6 => Some(JoypadButton::RIGHT),
+        Keycode::Kp0 => Some(JoypadButton::SELECT),
+        Keycode::KpEnter => Some(JoypadButton::START),
+        Keycode::Kp2 => Some(JoypadButton::BUTTON_A),
+        Keycode::Kp1 => Some(JoypadButton::BUTTON_B),
+        _ => None,
+    }
+}
+
+/// Frontend actions on keys that no controller uses
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Hotkey {
+    SelectSlot(u8),
+    SaveState,
+    LoadState,
+    /// Pressed or released, the game plays backwards while it is held
+    Rewind(bool),
+    /// Window closed or Escape pressed
+    Quit,
+}
+
+// Number keys pick a save slot, F5 saves, F9 loads and Backspace rewinds
+fn hotkey(key: Keycode) -> Option<Hotkey> {
+    const SLOT_KEYS: [Keycode; 10] = [
+        Keycode::Num0,
+        Keycode::Num1,
+        Keycode::Num2,
+        Keycode::Num3,
+        Keycode::Num4,
+        Keycode::Num5,
+        Keycode::Num6,
+        Keycode::Num7,
+        Keycode::Num8,
+        Keycode::Num9,
+    ];
+    if let Some(slot) = SLOT_KEYS.iter().position(|&slot_key| slot_key == key) {
+        return Some(Hotkey::SelectSlot(slot as u8));
+    }
+    match key {
+        Keycode::F5 => Some(Hotkey::SaveState),
+        Keycode::F9 => Some(Hotkey::LoadState),
+        Keycode::Backspace => Some(Hotkey::Rewind(true)),
+        _ => None,
+    }
+}
+
+fn set_button(joypad1: &mut Joypad, joypad2: &mut Joypad, key: Keycode, pressed: bool) {
+    if let Some(button) = player1_button(key) {
+        joypad1.set_button_pressed_status(button, pressed);
+    }
+    if let Some(button) = player2_button(key) {
+        joypad2.set_button_pressed_status(button, pressed);
+    }
+}
+
+/// Updates the controllers from the keyboard and returns the hotkeys pressed since the last call.
+pub fn handle_user_input(
+    event_pump: &mut EventPump,
+    joypad1: &mut Joypad,
+    joypad2: &mut Joypad,
+) -> Vec<Hotkey> {
+    let mut hotkeys = vec![];
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. }
             | Event::KeyDown {
                 keycode: Some(Keycode::Escape),
                 ..
-            } => std::process::exit(0),
+            } => hotkeys.push(Hotkey::Quit),
             Event::KeyDown {
-                keycode: Some(Keycode::W),
+                keycode: Some(key),
+                repeat,
                 ..
             } => {
-                cpu.mem_write(0xff, 0x77);
+                set_button(joypad1, joypad2, key, true);
+                if let Some(hotkey) = hotkey(key).filter(|_| !repeat) {
+                    hotkeys.push(hotkey);
+                }
             }
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
-                ..
-            } => {
-                cpu.mem_write(0xff, 0x73);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::A),
-                ..
-            } => {
-                cpu.mem_write(0xff, 0x61);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::D),
-                ..
+            Event::KeyUp {
+                keycode: Some(key), ..
             } => {
-                cpu.mem_write(0xff, 0x64);
+                set_button(joypad1, joypad2, key, false);
+                if key == Keycode::Backspace {
+                    hotkeys.push(Hotkey::Rewind(false));
+                }
             }
             _ => { /* do nothing */ }
         }
     }
-}
-
-fn color(byte: u8) -> Color {
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
-    }
-}
-
-pub fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
-        }
-        frame_idx += 3;
-    }
-    update
+    hotkeys
 }